@@ -1,5 +1,3 @@
-use gps;
-
 fn main() -> std::io::Result<()> {
     let key_values = [
         ("shop".to_owned(), "bakery".to_string()),
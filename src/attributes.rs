@@ -0,0 +1,152 @@
+// what we know about each edge of the map, stored on one byte in the tiles:
+// road class on bits 0 to 3, unpaved on bit 4 and oneway on bits 5 and 6.
+use std::collections::HashMap;
+
+use crate::{decoder::DecodeError, profile::UNPAVED_SURFACES};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RoadClass {
+    #[default]
+    Other,
+    Motorway,
+    Trunk,
+    Primary,
+    Secondary,
+    Tertiary,
+    Residential,
+    Service,
+    Track,
+    Cycleway,
+    Path,
+    Footway,
+    Steps,
+    Piste,
+    Aerialway,
+}
+
+// direction in which we can go, relative to the stored edge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Oneway {
+    #[default]
+    No,
+    Forward,
+    Backward,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct EdgeAttributes {
+    pub class: RoadClass,
+    pub unpaved: bool,
+    pub oneway: Oneway,
+}
+
+const ROAD_CLASSES: [RoadClass; 15] = [
+    RoadClass::Other,
+    RoadClass::Motorway,
+    RoadClass::Trunk,
+    RoadClass::Primary,
+    RoadClass::Secondary,
+    RoadClass::Tertiary,
+    RoadClass::Residential,
+    RoadClass::Service,
+    RoadClass::Track,
+    RoadClass::Cycleway,
+    RoadClass::Path,
+    RoadClass::Footway,
+    RoadClass::Steps,
+    RoadClass::Piste,
+    RoadClass::Aerialway,
+];
+
+impl RoadClass {
+    fn from_tags(tags: &HashMap<&str, &str>) -> Self {
+        if tags.contains_key("aerialway") {
+            return RoadClass::Aerialway;
+        }
+        if tags.contains_key("piste:type") && !tags.contains_key("highway") {
+            return RoadClass::Piste;
+        }
+        match tags.get("highway").copied().unwrap_or_default() {
+            "motorway" | "motorway_link" => RoadClass::Motorway,
+            "trunk" | "trunk_link" => RoadClass::Trunk,
+            "primary" | "primary_link" => RoadClass::Primary,
+            "secondary" | "secondary_link" => RoadClass::Secondary,
+            "tertiary" | "tertiary_link" => RoadClass::Tertiary,
+            "residential" | "unclassified" | "living_street" | "road" => RoadClass::Residential,
+            "service" => RoadClass::Service,
+            "track" => RoadClass::Track,
+            "cycleway" => RoadClass::Cycleway,
+            "path" | "bridleway" => RoadClass::Path,
+            "footway" | "pedestrian" | "corridor" => RoadClass::Footway,
+            "steps" => RoadClass::Steps,
+            _ => RoadClass::Other,
+        }
+    }
+}
+
+impl EdgeAttributes {
+    // attributes of the edges of a way with given tags (in the way's direction)
+    pub fn from_tags(tags: &HashMap<&str, &str>) -> Self {
+        let class = RoadClass::from_tags(tags);
+        let unpaved = match tags.get("surface") {
+            Some(surface) => UNPAVED_SURFACES.contains(surface),
+            // tracks are unpaved unless told otherwise
+            None => class == RoadClass::Track && tags.get("tracktype") != Some(&"grade1"),
+        };
+        let oneway = match tags.get("oneway").copied() {
+            Some("yes" | "1" | "true") => Oneway::Forward,
+            Some("-1" | "reverse") => Oneway::Backward,
+            Some(_) => Oneway::No,
+            None if tags.get("junction") == Some(&"roundabout") || class == RoadClass::Motorway => {
+                Oneway::Forward
+            }
+            None => Oneway::No,
+        };
+        EdgeAttributes {
+            class,
+            unpaved,
+            oneway,
+        }
+    }
+
+    // same edge, stored the other way around
+    pub fn reversed(self) -> Self {
+        let oneway = match self.oneway {
+            Oneway::No => Oneway::No,
+            Oneway::Forward => Oneway::Backward,
+            Oneway::Backward => Oneway::Forward,
+        };
+        EdgeAttributes { oneway, ..self }
+    }
+}
+
+impl From<EdgeAttributes> for u8 {
+    fn from(attributes: EdgeAttributes) -> Self {
+        attributes.class as u8 | (attributes.unpaved as u8) << 4 | (attributes.oneway as u8) << 5
+    }
+}
+
+impl TryFrom<u8> for EdgeAttributes {
+    type Error = DecodeError;
+
+    fn try_from(byte: u8) -> Result<Self, DecodeError> {
+        let invalid = || DecodeError::InvalidData(format!("invalid edge attributes {byte:#04x}"));
+        let class = *ROAD_CLASSES
+            .get((byte & 0x0f) as usize)
+            .ok_or_else(invalid)?;
+        let oneway = match (byte >> 5) & 0b11 {
+            0 => Oneway::No,
+            1 => Oneway::Forward,
+            2 => Oneway::Backward,
+            _ => return Err(invalid()),
+        };
+        if byte & 0x80 != 0 {
+            return Err(invalid());
+        }
+        Ok(EdgeAttributes {
+            class,
+            unpaved: byte & 0x10 != 0,
+            oneway,
+        })
+    }
+}
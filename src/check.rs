@@ -0,0 +1,433 @@
+// structural checks on .gps files, to tell apart broken files from broken readers.
+use std::io::Read;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use itertools::Itertools;
+
+use crate::{
+    decoder::DecodeError,
+    gpx::{load_heights, load_times},
+    map::{
+        load_attributes, load_compressed_groups, load_gps_header, load_sub_tiles, read_block,
+        TilesOffsets,
+    },
+    utils::read_varint,
+    BlockType,
+};
+
+// one broken invariant, located by its block (and tile if any)
+#[derive(Debug, Clone)]
+pub struct Violation {
+    pub block: usize,
+    pub block_type: Option<BlockType>,
+    pub tile: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "block {}", self.block)?;
+        if let Some(block_type) = self.block_type {
+            write!(f, " ({block_type:?})")?;
+        }
+        if let Some(tile) = self.tile {
+            write!(f, ", tile {tile}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// collect all violations found in the file.
+// we stop at the first block we cannot read since following blocks are lost.
+pub fn check_gps<R: Read>(reader: &mut R) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    let blocks_number = match load_gps_header(reader) {
+        Ok(blocks_number) => blocks_number,
+        Err(e) => {
+            violations.push(Violation {
+                block: 0,
+                block_type: None,
+                tile: None,
+                message: format!("invalid header: {e}"),
+            });
+            return violations;
+        }
+    };
+    let mut points_number = None;
+    for block_number in 0..blocks_number {
+        let (block_type, block) = match read_block(reader, block_number) {
+            Ok(block) => block,
+            Err(e) => {
+                violations.push(Violation {
+                    block: block_number,
+                    block_type: None,
+                    tile: None,
+                    message: e.to_string(),
+                });
+                break;
+            }
+        };
+        let Ok(block_type) = BlockType::try_from(block_type) else {
+            continue; // unknown blocks are allowed
+        };
+        let mut report = |tile: Option<usize>, message: String| {
+            violations.push(Violation {
+                block: block_number,
+                block_type: Some(block_type),
+                tile,
+                message,
+            })
+        };
+        let reader = &mut block.as_slice();
+        match block_type {
+            BlockType::Tiles => check_tiles(reader, false, &mut report),
+            BlockType::CompressedTiles => check_tiles(reader, true, &mut report),
+            BlockType::Path => points_number = check_path(reader, &mut report),
+            BlockType::Interests => check_interests(reader, &mut report),
+            BlockType::Heights | BlockType::Times => {
+                let Some(points_number) = points_number else {
+                    report(None, "no path before this block".to_owned());
+                    continue;
+                };
+                let loaded = if block_type == BlockType::Heights {
+                    load_heights(points_number, reader).map(|_| ())
+                } else {
+                    load_times(points_number, reader).map(|_| ())
+                };
+                if loaded.is_err() || !reader.is_empty() {
+                    report(
+                        None,
+                        format!("values count does not match the {points_number} path points"),
+                    );
+                }
+            }
+            BlockType::Streets
+            | BlockType::InterestsDetails
+            | BlockType::Metadata
+            | BlockType::Instructions => (),
+        }
+    }
+    violations
+}
+
+fn check_tiles<F: FnMut(Option<usize>, String)>(
+    reader: &mut &[u8],
+    compressed: bool,
+    report: &mut F,
+) {
+    let mut header = || -> Result<_, DecodeError> {
+        let mut color = [0u8; 3];
+        reader.read_exact(&mut color)?;
+        let first_tile = (
+            reader.read_i32::<LittleEndian>()? as isize,
+            reader.read_i32::<LittleEndian>()? as isize,
+        );
+        let grid_size = (
+            reader.read_u32::<LittleEndian>()? as usize,
+            reader.read_u32::<LittleEndian>()? as usize,
+        );
+        let start_coordinates = (
+            reader.read_f64::<LittleEndian>()?,
+            reader.read_f64::<LittleEndian>()?,
+        );
+        let side = reader.read_f64::<LittleEndian>()?;
+        let tiles_offsets = TilesOffsets::load(reader, grid_size)?;
+        let sub_tiles_ends = load_sub_tiles(reader, grid_size)?;
+        load_attributes(reader, tiles_offsets.entries_number())?;
+        Ok((
+            first_tile,
+            grid_size,
+            start_coordinates,
+            side,
+            tiles_offsets,
+            sub_tiles_ends,
+        ))
+    };
+    let (first_tile, grid_size, start_coordinates, side, tiles_offsets, sub_tiles_ends) =
+        match header() {
+            Ok(header) => header,
+            Err(e) => return report(None, format!("invalid header: {e}")),
+        };
+    let finite =
+        side.is_finite() && start_coordinates.0.is_finite() && start_coordinates.1.is_finite();
+    if !finite || side <= 0. {
+        return report(
+            None,
+            format!("invalid tiles geometry: start {start_coordinates:?}, side {side}"),
+        );
+    }
+
+    let payload = if compressed {
+        match load_compressed_groups(reader, &tiles_offsets) {
+            Ok(payload) => payload,
+            Err(e) => return report(None, format!("invalid compressed groups: {e}")),
+        }
+    } else {
+        reader.to_vec()
+    };
+
+    let tiles_number = grid_size.0 * grid_size.1;
+    let mut previous = (None, 0);
+    let mut tiles = Vec::new();
+    for (&tile, &end) in tiles_offsets
+        .non_empty_tiles
+        .iter()
+        .zip(&tiles_offsets.non_empty_tiles_ends)
+    {
+        let end = end * tiles_offsets.entry_size;
+        let start = previous.1;
+        if tile >= tiles_number {
+            report(
+                Some(tile),
+                format!(
+                    "tile index outside the {}x{} grid",
+                    grid_size.0, grid_size.1
+                ),
+            );
+        } else if previous.0.is_some_and(|p| p >= tile) {
+            report(Some(tile), "tiles indices are not increasing".to_owned());
+        }
+        // non-empty tiles ends are strictly increasing
+        if end <= start {
+            report(
+                Some(tile),
+                format!("tile end {end} is not after its start {start}"),
+            );
+        } else if end > payload.len() {
+            report(
+                Some(tile),
+                format!("tile end {end} is past the {} bytes payload", payload.len()),
+            );
+        } else if tile < tiles_number {
+            tiles.push((tile, start, end));
+        }
+        previous = (Some(tile), end.max(start));
+    }
+    if !compressed && previous.1 != payload.len() {
+        report(
+            None,
+            format!(
+                "tiles end at {} but payload has {} bytes",
+                previous.1,
+                payload.len()
+            ),
+        );
+    }
+
+    for (tile, ends) in sub_tiles_ends.iter().sorted() {
+        let size = tiles
+            .iter()
+            .find(|(t, _, _)| t == tile)
+            .map(|(_, start, end)| end - start);
+        if size.is_none_or(|size| ends.last().copied().unwrap_or_default() != size) {
+            report(
+                Some(*tile),
+                "sub-tiles do not cover the tile content".to_owned(),
+            );
+        }
+    }
+
+    for (tile, start, end) in tiles {
+        if (end - start) % tiles_offsets.entry_size != 0 {
+            report(Some(tile), "tile contains a partial way".to_owned());
+        }
+        // decode as the watch does, from the start coordinates
+        let (tile_x, tile_y) = (tile % grid_size.0, tile / grid_size.0);
+        let origin = (
+            start_coordinates.0 + tile_x as f64 * side,
+            start_coordinates.1 + tile_y as f64 * side,
+        );
+        let bounds_tile = (
+            first_tile.0 + tile_x as isize,
+            first_tile.1 + tile_y as isize,
+        );
+        check_tile_coordinates(
+            tile,
+            payload[start..end].chunks_exact(2).map(|c| (c[0], c[1])),
+            origin,
+            bounds_tile,
+            side,
+            report,
+        );
+    }
+}
+
+// decode coordinates relative to origin and report the first one
+// outside the bounds of the given tile (in tiles of the grid).
+fn check_tile_coordinates<F: FnMut(Option<usize>, String)>(
+    tile: usize,
+    coordinates: impl Iterator<Item = (u8, u8)>,
+    origin: (f64, f64),
+    (tile_x, tile_y): (isize, isize),
+    side: f64,
+    report: &mut F,
+) {
+    // rounding errors are fine
+    let epsilon = side * 1e-6;
+    let (xmin, ymin) = (tile_x as f64 * side, tile_y as f64 * side);
+    let outside = |v: f64, min: f64| v < min - epsilon || v > min + side + epsilon;
+    if let Some((x, y)) = coordinates
+        .map(|(cx, cy)| {
+            (
+                origin.0 + cx as f64 / 255. * side,
+                origin.1 + cy as f64 / 255. * side,
+            )
+        })
+        .find(|(x, y)| outside(*x, xmin) || outside(*y, ymin))
+    {
+        report(
+            Some(tile),
+            format!(
+                "coordinates ({x}, {y}) outside the tile ({xmin}, {ymin}) -> ({}, {})",
+                xmin + side,
+                ymin + side
+            ),
+        );
+    }
+}
+
+// return the number of path points if we could read them
+fn check_path<F: FnMut(Option<usize>, String)>(
+    reader: &mut &[u8],
+    report: &mut F,
+) -> Option<usize> {
+    let mut points = || -> Result<usize, DecodeError> {
+        let points_number = reader.read_u32::<LittleEndian>()? as usize;
+        reader.read_f64::<LittleEndian>()?;
+        for _ in 0..2 * points_number {
+            read_varint(reader)?;
+        }
+        Ok(points_number)
+    };
+    let points_number = match points() {
+        Ok(points_number) => points_number,
+        Err(e) => {
+            report(None, format!("invalid points: {e}"));
+            return None;
+        }
+    };
+
+    let bitmap_size = points_number.div_ceil(8);
+    if reader.len() < bitmap_size {
+        report(
+            None,
+            format!(
+                "waypoints bitmap has {} bytes instead of {bitmap_size}",
+                reader.len()
+            ),
+        );
+        return Some(points_number);
+    }
+    let (bitmap, breaks) = reader.split_at(bitmap_size);
+    if points_number % 8 != 0 && bitmap.last().is_some_and(|b| b >> (points_number % 8) != 0) {
+        report(
+            None,
+            "waypoints bitmap has bits set past the last point".to_owned(),
+        );
+    }
+    let mut breaks = breaks;
+    let mut parse_breaks = || -> Result<(), DecodeError> {
+        let breaks_number = read_varint(&mut breaks)?;
+        let mut point = 0i64;
+        for _ in 0..breaks_number {
+            // breaks are strictly increasing and inside the path
+            let delta = read_varint(&mut breaks)?;
+            point = Some(delta)
+                .filter(|delta| *delta > 0)
+                .and_then(|delta| point.checked_add(delta))
+                .filter(|p| *p < points_number as i64)
+                .ok_or_else(|| {
+                    DecodeError::BadSize(format!("invalid break after point {point}"))
+                })?;
+        }
+        Ok(())
+    };
+    if let Err(e) = parse_breaks() {
+        report(None, format!("invalid breaks: {e}"));
+    } else if !breaks.is_empty() {
+        report(
+            None,
+            format!("{} unexpected bytes after the breaks", breaks.len()),
+        );
+    }
+    Some(points_number)
+}
+
+fn check_interests<F: FnMut(Option<usize>, String)>(reader: &mut &[u8], report: &mut F) {
+    let mut header = || -> Result<_, DecodeError> {
+        let first_tile = (
+            reader.read_i32::<LittleEndian>()? as isize,
+            reader.read_i32::<LittleEndian>()? as isize,
+        );
+        let grid_size = (
+            reader.read_u32::<LittleEndian>()? as usize,
+            reader.read_u32::<LittleEndian>()? as usize,
+        );
+        let start_coordinates = (
+            reader.read_f64::<LittleEndian>()?,
+            reader.read_f64::<LittleEndian>()?,
+        );
+        let side = reader.read_f64::<LittleEndian>()?;
+        let tiles_offsets = TilesOffsets::load(reader, grid_size)?;
+        // type and coordinates in the tile for each interest
+        tiles_offsets.validate(grid_size, 3)?;
+        Ok((
+            first_tile,
+            grid_size,
+            start_coordinates,
+            side,
+            tiles_offsets,
+        ))
+    };
+    let (first_tile, grid_size, start_coordinates, side, tiles_offsets) = match header() {
+        Ok(header) => header,
+        Err(e) => return report(None, format!("invalid tiles offsets: {e}")),
+    };
+    let finite =
+        side.is_finite() && start_coordinates.0.is_finite() && start_coordinates.1.is_finite();
+    if !finite || side <= 0. {
+        return report(
+            None,
+            format!("invalid interests geometry: start {start_coordinates:?}, side {side}"),
+        );
+    }
+    if tiles_offsets.end_offset() != reader.len() {
+        report(
+            None,
+            format!(
+                "interests end at {} but block has {} bytes left",
+                tiles_offsets.end_offset(),
+                reader.len()
+            ),
+        );
+    }
+    let mut start = 0;
+    for (&tile, &end) in tiles_offsets
+        .non_empty_tiles
+        .iter()
+        .zip(&tiles_offsets.non_empty_tiles_ends)
+    {
+        let end = (end * tiles_offsets.entry_size).min(reader.len());
+        let (tile_x, tile_y) = (tile % grid_size.0, tile / grid_size.0);
+        let origin = (
+            start_coordinates.0 + tile_x as f64 * side,
+            start_coordinates.1 + tile_y as f64 * side,
+        );
+        let bounds_tile = (
+            first_tile.0 + tile_x as isize,
+            first_tile.1 + tile_y as isize,
+        );
+        check_tile_coordinates(
+            tile,
+            reader[start.min(end)..end]
+                .iter()
+                .tuples()
+                .map(|(_, cx, cy)| (*cx, *cy)),
+            origin,
+            bounds_tile,
+            side,
+            report,
+        );
+        start = end;
+    }
+}
@@ -0,0 +1,205 @@
+// decode .gps files back into their blocks.
+use std::{collections::HashSet, io::Read, path::Path};
+
+use crate::{
+    gpx::{load_heights, load_path, load_times},
+    instructions::{load_instructions, Instruction},
+    interests::{load_interests_details, load_tiled_interests, InterestDetails},
+    map::{load_gps_header, read_block, BlockType},
+    metadata::load_metadata,
+    Map, Node,
+};
+
+/// Why a .gps file (or one of its blocks) could not be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// reading failed for a reason unrelated to the content.
+    Io(std::io::Error),
+    /// data ended before the end of what we were decoding.
+    Truncated,
+    NotGps,
+    UnsupportedVersion(u16),
+    UnknownBlockType(u8),
+    BadChecksum,
+    /// a size, count or offset which does not match the data.
+    BadSize(String),
+    TileOutOfRange {
+        tile: usize,
+        tiles_number: usize,
+    },
+    /// any other invalid content (unknown encodings, bad strings...).
+    InvalidData(String),
+    /// error found while decoding given block of the file.
+    InBlock {
+        block: usize,
+        block_type: u8,
+        error: Box<DecodeError>,
+    },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Io(e) => write!(f, "{e}"),
+            DecodeError::Truncated => write!(f, "data is truncated"),
+            DecodeError::NotGps => write!(f, "not a .gps file"),
+            DecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported .gps format version {version}")
+            }
+            DecodeError::UnknownBlockType(block_type) => {
+                write!(f, "invalid block type {block_type}")
+            }
+            DecodeError::BadChecksum => write!(f, "corrupted (bad checksum)"),
+            DecodeError::BadSize(message) | DecodeError::InvalidData(message) => {
+                write!(f, "{message}")
+            }
+            DecodeError::TileOutOfRange { tile, tiles_number } => {
+                write!(f, "tile {tile} is outside the grid ({tiles_number} tiles)")
+            }
+            DecodeError::InBlock {
+                block,
+                block_type,
+                error,
+            } => {
+                let block_name = BlockType::try_from(*block_type)
+                    .map(|t| format!("{t:?}"))
+                    .unwrap_or_else(|_| format!("type {block_type}"));
+                write!(f, "block {block} ({block_name}): {error}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Io(e) => Some(e),
+            DecodeError::InBlock { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            DecodeError::Truncated
+        } else {
+            DecodeError::Io(e)
+        }
+    }
+}
+
+impl From<DecodeError> for std::io::Error {
+    fn from(e: DecodeError) -> Self {
+        match e {
+            DecodeError::Io(e) => e,
+            DecodeError::Truncated => std::io::Error::new(std::io::ErrorKind::UnexpectedEof, e),
+            e => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
+        }
+    }
+}
+
+/// Everything stored in a .gps file.
+#[derive(Default)]
+pub struct GpsFile {
+    /// all tiled layers in file order (the tiled path comes before the maps).
+    pub maps: Vec<Map>,
+    pub path: Option<Vec<Node>>,
+    pub waypoints: Option<HashSet<Node>>,
+    // indices of the path points starting a new segment
+    pub breaks: Vec<usize>,
+    pub interests: Vec<(usize, Node)>,
+    // tags of each interest, in the same order
+    pub interests_details: Vec<InterestDetails>,
+    pub heights: Option<Vec<f64>>,
+    // elapsed seconds at each path point
+    pub times: Option<Vec<u32>>,
+    pub instructions: Vec<Instruction>,
+    // (key, value) pairs in file order
+    pub metadata: Vec<(String, String)>,
+}
+
+impl GpsFile {
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, DecodeError> {
+        let mut gps = GpsFile::default();
+        let blocks_number = load_gps_header(reader)?;
+        for block_number in 0..blocks_number {
+            let (block_type, block) = read_block(reader, block_number)?;
+            gps.read_block(block_type, &block)
+                .map_err(|error| DecodeError::InBlock {
+                    block: block_number,
+                    block_type,
+                    error: Box::new(error),
+                })?;
+        }
+        Ok(gps)
+    }
+
+    fn read_block(&mut self, block_type: u8, block: &[u8]) -> Result<(), DecodeError> {
+        let Ok(block_type) = BlockType::try_from(block_type) else {
+            // written by a more recent version, we can skip it
+            return Ok(());
+        };
+        let points_number = self.path.as_ref().map(|p| p.len());
+        // path related blocks must all come after a single path block
+        let points_number = match (block_type, points_number) {
+            (BlockType::Path, Some(_)) => {
+                return Err(DecodeError::InvalidData("several paths".to_owned()))
+            }
+            (BlockType::Heights | BlockType::Times | BlockType::Instructions, None) => {
+                return Err(DecodeError::InvalidData(
+                    "no path before this block".to_owned(),
+                ))
+            }
+            (_, points_number) => points_number.unwrap_or_default(),
+        };
+        let reader = &mut &block[..];
+        match block_type {
+            BlockType::Tiles => self.maps.push(Map::load_tiles(reader, false)?),
+            BlockType::CompressedTiles => self.maps.push(Map::load_tiles(reader, true)?),
+            BlockType::Streets => self
+                .maps
+                .last_mut()
+                .ok_or_else(|| DecodeError::InvalidData("streets without a map".to_owned()))?
+                .load_streets(reader)?,
+            BlockType::Path => {
+                let (path, waypoints, breaks) = load_path(reader)?;
+                self.path = Some(path);
+                self.waypoints = Some(waypoints);
+                self.breaks = breaks;
+            }
+            BlockType::Times => self.times = Some(load_times(points_number, reader)?),
+            BlockType::Instructions => {
+                let instructions = load_instructions(reader)?;
+                if let Some(instruction) = instructions.iter().find(|i| i.point >= points_number) {
+                    return Err(DecodeError::BadSize(format!(
+                        "instruction at point {} but path has {points_number} points",
+                        instruction.point
+                    )));
+                }
+                self.instructions = instructions;
+            }
+            BlockType::Metadata => self.metadata = load_metadata(reader)?,
+            BlockType::Interests => self.interests = load_tiled_interests(reader)?,
+            BlockType::InterestsDetails => {
+                let details = load_interests_details(reader)?;
+                if details.len() != self.interests.len() {
+                    return Err(DecodeError::BadSize(format!(
+                        "{} interests details for {} interests",
+                        details.len(),
+                        self.interests.len()
+                    )));
+                }
+                self.interests_details = details;
+            }
+            BlockType::Heights => self.heights = Some(load_heights(points_number, reader)?),
+        }
+        Ok(())
+    }
+}
+
+pub fn load_gps_file<P: AsRef<Path>>(path: P) -> Result<GpsFile, DecodeError> {
+    let mut reader = std::io::BufReader::new(std::fs::File::open(path)?);
+    GpsFile::read(&mut reader)
+}
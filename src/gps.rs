@@ -6,22 +6,43 @@ use std::{
 use wasm_bindgen::prelude::*;
 
 use crate::{
-    gpx::{save_heights, save_path},
-    parse_gpx_points, save_svg, save_tiled_interests, simplify_path,
+    gpx::{path_heights, save_heights, save_path, save_times, segments_ranges, GpxContent},
+    instructions::{path_instructions, save_instructions},
+    interests::{
+        save_interests_details, InterestDetails, DEFAULT_INTERESTS_TAGS, GPX_WAYPOINT_INTEREST,
+    },
+    map::save_gps_header,
+    metadata::{creation_time, elevation_gain, path_length, save_metadata, OSM_ATTRIBUTION},
+    osm::{OsmSource, Overpass},
+    parse_gpx_points, save_svg, save_tiled_interests, simplify_path_indices,
     svg::{save_svg_to_writer, UniColorNodes},
-    Map, Node, Svg, SvgW,
+    Map, Node, Profile, Svg, SvgW,
 };
 
 #[wasm_bindgen]
 pub struct Gps {
-    ski: bool,
+    profile: Profile,
     path: Option<Vec<Node>>,
     waypoints: Option<HashSet<Node>>,
-    map_polygon: Vec<Node>,
-    interests: Vec<(usize, Node)>,
+    // indices of the path points starting a new segment
+    breaks: Vec<usize>,
+    // gpx waypoints away from the path
+    gpx_interests: Vec<Node>,
+    // area to request, one polygon per group of nearby segments
+    map_polygons: Vec<Vec<Node>>,
+    interests: Vec<(usize, Node, InterestDetails)>,
+    // tags of interests we save
+    interests_tags: Vec<String>,
     maps: Vec<Map>,
     heights: Option<HashMap<Node, f64>>,
+    // one entry per path point
+    times: Option<Vec<Option<f64>>>,
+    name: Option<String>,
+    precise_heights: bool,
+    compressed_tiles: bool,
+    edge_attributes: bool,
     autodetect_waypoints: bool,
+    overpass: Overpass,
 }
 
 #[wasm_bindgen]
@@ -29,6 +50,50 @@ pub fn disable_elevation(gps: &mut Gps) {
     gps.heights = None;
 }
 
+// store heights in decimeters instead of meters
+#[wasm_bindgen]
+pub fn enable_precise_elevation(gps: &mut Gps) {
+    gps.precise_heights = true;
+}
+
+// compress tiles by groups (smaller files, a bit slower on the watch)
+#[wasm_bindgen]
+pub fn enable_tiles_compression(gps: &mut Gps) {
+    gps.compressed_tiles = true;
+}
+
+// save road class, surface and oneway of each edge of the maps.
+// must be enabled before the maps are loaded.
+#[wasm_bindgen]
+pub fn enable_edge_attributes(gps: &mut Gps) {
+    gps.edge_attributes = true;
+}
+
+// use another overpass server (a self-hosted mirror for example)
+#[wasm_bindgen]
+pub fn set_overpass_endpoint(gps: &mut Gps, endpoint: &str) {
+    gps.overpass.endpoint = endpoint.to_owned();
+}
+
+#[wasm_bindgen]
+pub fn set_overpass_user_agent(gps: &mut Gps, user_agent: &str) {
+    gps.overpass.user_agent = user_agent.to_owned();
+}
+
+// give up on overpass requests after given number of seconds
+#[wasm_bindgen]
+pub fn set_overpass_timeout(gps: &mut Gps, seconds: f64) {
+    gps.overpass.timeout = Some(std::time::Duration::from_secs_f64(seconds));
+}
+
+// also save given tag of interests (on top of their name and opening hours)
+#[wasm_bindgen]
+pub fn add_interests_tag(gps: &mut Gps, key: &str) {
+    if !gps.interests_tags.iter().any(|tag| tag == key) {
+        gps.interests_tags.push(key.to_owned());
+    }
+}
+
 #[wasm_bindgen]
 pub fn get_gps_map_svg(gps: &Gps) -> String {
     let mut svg_string: Vec<u8> = Vec::new();
@@ -63,7 +128,7 @@ pub fn get_gps_map_svg(gps: &Gps) -> String {
             .map(|m| m as &dyn Svg<_>)
             .chain(std::iter::once(&path_slice as &dyn Svg<_>))
             .chain(std::iter::once(&UniColorNodes(
-                gps.interests.iter().map(|(_, n)| *n).collect::<Vec<_>>(),
+                gps.interests.iter().map(|(_, n, _)| *n).collect::<Vec<_>>(),
             ) as &dyn Svg<_>)),
         true,
     )
@@ -72,13 +137,18 @@ pub fn get_gps_map_svg(gps: &Gps) -> String {
 }
 
 #[wasm_bindgen]
+// latitudes and longitudes of all polygons, separated by a NaN pair
 pub fn get_polygon(gps: &Gps) -> Vec<f64> {
-    gps.map_polygon.iter().flat_map(|n| [n.y, n.x]).collect()
+    gps.map_polygons
+        .iter()
+        .map(|polygon| polygon.iter().flat_map(|n| [n.y, n.x]).collect::<Vec<_>>())
+        .collect::<Vec<_>>()
+        .join(&[f64::NAN, f64::NAN][..])
 }
 
 #[wasm_bindgen]
 pub fn has_heights(gps: &Gps) -> bool {
-    gps.heights.is_some()
+    gps.heights.as_ref().is_some_and(|h| !h.is_empty())
 }
 
 #[wasm_bindgen]
@@ -97,6 +167,7 @@ pub fn get_gps_content(gps: &Gps) -> Vec<u8> {
 }
 
 #[wasm_bindgen]
+#[allow(clippy::too_many_arguments)]
 pub async fn request_map(
     gps: &mut Gps,
     key1: &str,
@@ -119,10 +190,10 @@ pub async fn request_map(
 }
 
 #[wasm_bindgen]
-pub fn load_gps_from_string(input: &str, autodetect_waypoints: bool) -> Gps {
+pub fn load_gps_from_string(input: &str, autodetect_waypoints: bool) -> Result<Gps, String> {
     console_error_panic_hook::set_once();
     let reader = std::io::Cursor::new(input);
-    Gps::new(reader, autodetect_waypoints, crate::map::DEFAULT_SIDE)
+    Gps::new(reader, autodetect_waypoints, crate::map::DEFAULT_SIDE).map_err(|e| e.to_string())
 }
 
 #[wasm_bindgen]
@@ -134,45 +205,75 @@ pub fn gps_from_area(xmin: f64, ymin: f64, xmax: f64, ymax: f64, ski: bool) -> G
             Node::new(xmax, ymax),
             Node::new(xmin, ymax),
         ],
-        ski,
+        if ski {
+            Profile::Ski
+        } else {
+            Profile::default()
+        },
     )
 }
 
+// which ways to keep on the map
+#[wasm_bindgen]
+pub fn set_profile(gps: &mut Gps, profile: Profile) {
+    gps.profile = profile;
+}
+
 pub fn load_gps_from_file(path: &str, autodetect_waypoints: bool) -> std::io::Result<Gps> {
     let gpx_file = std::fs::File::open(path)?;
     let gpx_reader = std::io::BufReader::new(gpx_file);
-    Ok(Gps::new(
-        gpx_reader,
-        autodetect_waypoints,
-        crate::map::DEFAULT_SIDE,
-    ))
+    Gps::new(gpx_reader, autodetect_waypoints, crate::map::DEFAULT_SIDE)
 }
 
 impl Gps {
-    fn new<R: Read>(gpx_reader: R, mut autodetect_waypoints: bool, side: f64) -> Self {
+    fn new<R: Read>(
+        gpx_reader: R,
+        mut autodetect_waypoints: bool,
+        side: f64,
+    ) -> std::io::Result<Self> {
         // load all points composing the trace and mark commented points
         // as special waypoints.
-        let (mut waypoints, p, heights) = parse_gpx_points(gpx_reader);
+        let GpxContent {
+            points: p,
+            mut waypoints,
+            breaks,
+            heights,
+            times,
+            name,
+            interests: gpx_interests,
+        } = parse_gpx_points(gpx_reader)?;
 
-        // brouter has a tendency to generate small loops
-        // around its waypoints
-        // we remove them here.
-        let p = p
-            .first()
-            .cloned()
-            .into_iter()
-            .chain(p.windows(3).filter_map(|w| {
-                if w.first() == w.last() && w[0].distance_to(&w[1]) < 0.00015 {
-                    None
-                } else {
-                    Some(w[1])
-                }
-            }))
-            .chain(p.last().cloned())
-            .collect::<Vec<_>>();
+        if p.is_empty() {
+            // only waypoints, take the area around them
+            let (xmin, xmax) = gpx_interests
+                .iter()
+                .map(|n| n.x)
+                .minmax_by(|a, b| a.partial_cmp(b).unwrap())
+                .into_option()
+                .ok_or_else(|| {
+                    std::io::Error::new(std::io::ErrorKind::InvalidData, "no points in gpx file")
+                })?;
+            let (ymin, ymax) = gpx_interests
+                .iter()
+                .map(|n| n.y)
+                .minmax_by(|a, b| a.partial_cmp(b).unwrap())
+                .into_option()
+                .unwrap();
+            let margin = side * 2.;
+            let mut gps = Gps::from_area(
+                vec![
+                    Node::new(xmin - margin, ymin - margin),
+                    Node::new(xmax + margin, ymin - margin),
+                    Node::new(xmax + margin, ymax + margin),
+                    Node::new(xmin - margin, ymax + margin),
+                ],
+                Profile::default(),
+            );
+            gps.gpx_interests = gpx_interests;
+            gps.name = name;
+            return Ok(gps);
+        }
 
-        // detect sharp turns before path simplification to keep them
-        // detect_sharp_turns(&p, &mut waypoints);
         waypoints.insert(p.first().copied().unwrap());
         waypoints.insert(p.last().copied().unwrap());
 
@@ -181,47 +282,108 @@ impl Gps {
             autodetect_waypoints = false;
         }
 
-        let rp = simplify_path_around_waypoints(&p, &waypoints);
-
-        crate::log("inflating polyline");
-        let map_polygon = inflate_polyline(&rp, side * 2.); // two tiles on each side
-        crate::log("computed polygon");
-        Gps {
-            ski: false,
+        let mut gps = Gps {
+            profile: Profile::default(),
             waypoints: Some(waypoints),
-            path: Some(if autodetect_waypoints { p } else { rp }),
-            map_polygon,
+            breaks,
+            gpx_interests,
+            path: Some(p),
+            map_polygons: Vec::new(),
             maps: Vec::new(),
             interests: Vec::new(),
             heights: Some(heights),
+            times: Some(times),
+            name,
+            precise_heights: false,
+            compressed_tiles: false,
+            edge_attributes: false,
             autodetect_waypoints,
+            overpass: Overpass::default(),
+            interests_tags: DEFAULT_INTERESTS_TAGS.map(str::to_owned).to_vec(),
+        };
+
+        // brouter has a tendency to generate small loops
+        // around its waypoints
+        // we remove them here (inside each segment).
+        let p = gps.path.as_ref().unwrap();
+        let kept = (0..p.len())
+            .filter(|&i| {
+                i == 0
+                    || i + 1 == p.len()
+                    || gps.breaks.contains(&i)
+                    || gps.breaks.contains(&(i + 1))
+                    || !(p[i - 1] == p[i + 1] && p[i - 1].distance_to(&p[i]) < 0.00015)
+            })
+            .collect::<Vec<_>>();
+        gps.keep_path_points(&kept);
+
+        // detect sharp turns before path simplification to keep them
+        // detect_sharp_turns(&p, &mut waypoints);
+
+        let p = gps.path.as_ref().unwrap();
+        let kept = simplified_path_indices(p, gps.waypoints.as_ref().unwrap(), &gps.breaks);
+        let rp = keep_indices(p, &kept);
+        let rp_breaks = kept_breaks(&gps.breaks, &kept);
+
+        crate::log("inflating polyline");
+        // two tiles on each side
+        gps.map_polygons = inflate_polyline(&rp, &rp_breaks, side * 2.);
+        crate::log("computed polygon");
+        // path and times are simplified once waypoints are known
+        if !autodetect_waypoints {
+            gps.keep_path_points(&kept);
         }
+        Ok(gps)
     }
     pub fn detect_crossroads(&mut self) {
-        let (path, maps, waypoints) = (&mut self.path, &self.maps, &mut self.waypoints);
-        if let Some(path) = path {
-            if maps.len() == 1 {
-                if let Some(waypoints) = waypoints {
-                    if waypoints.len() <= 2 {
-                        // if we have two waypoints it's start and end
-                        maps[0].detect_crossroads(path, waypoints);
-                    }
-                }
-            } else {
-                eprintln!("TODO: we need to collapse all maps in order to detect crossroads");
+        let (Some(path), Some(waypoints)) = (&self.path, &mut self.waypoints) else {
+            return;
+        };
+        // route layers only repeat ways of the first map
+        if self.maps.len() == 1 || (!self.maps.is_empty() && self.profile != Profile::Ski) {
+            if waypoints.len() <= 2 {
+                // if we have two waypoints it's start and end
+                self.maps[0].detect_crossroads(path, waypoints, &self.breaks);
+                let kept = simplified_path_indices(path, waypoints, &self.breaks);
+                self.keep_path_points(&kept);
             }
+        } else {
+            eprintln!("TODO: we need to collapse all maps in order to detect crossroads");
+        }
+    }
+    // only keep path points (and their times) at given increasing indices
+    fn keep_path_points(&mut self, kept: &[usize]) {
+        if let Some(path) = &mut self.path {
+            *path = keep_indices(path, kept);
+        }
+        if let Some(times) = &mut self.times {
+            *times = keep_indices(times, kept);
         }
+        self.breaks = kept_breaks(&self.breaks, kept);
     }
-    pub fn from_area(area: Vec<Node>, ski: bool) -> Self {
+    // path tiles are not in there, they are built when saving
+    pub fn maps(&self) -> &[Map] {
+        &self.maps
+    }
+    pub fn from_area(area: Vec<Node>, profile: Profile) -> Self {
         Gps {
-            ski,
+            profile,
             waypoints: None,
+            breaks: Vec::new(),
+            gpx_interests: Vec::new(),
             path: None,
-            map_polygon: area,
+            map_polygons: vec![area],
             maps: Vec::new(),
             interests: Vec::new(),
             heights: None,
+            times: None,
+            name: None,
+            precise_heights: false,
+            compressed_tiles: false,
+            edge_attributes: false,
             autodetect_waypoints: false,
+            overpass: Overpass::default(),
+            interests_tags: DEFAULT_INTERESTS_TAGS.map(str::to_owned).to_vec(),
         }
     }
     pub async fn request_maps<P: AsRef<std::path::Path>>(
@@ -229,45 +391,79 @@ impl Gps {
         key_values: &[(String, String)],
         map_name: Option<P>,
     ) {
-        let (maps, interests) =
-            crate::request_maps_from(&self.map_polygon, key_values, map_name, self.ski)
-                .await
-                .expect("failed requesting map");
-        self.maps = maps;
-        self.interests = interests;
-        self.clip_maps();
-        if self.autodetect_waypoints {
-            self.detect_crossroads();
-        }
-        self.add_waypoints_to_interests();
+        let overpass = self.overpass.clone();
+        self.request_maps_with(key_values, map_name, &overpass)
+            .await
+    }
+    // same as request_maps but osm data comes from given source
+    pub async fn request_maps_with<P: AsRef<std::path::Path>, S: OsmSource>(
+        &mut self,
+        key_values: &[(String, String)],
+        map_name: Option<P>,
+        source: &S,
+    ) {
+        let (maps, interests) = crate::request_maps_from(
+            &self.map_polygons,
+            key_values,
+            map_name,
+            self.profile,
+            self.edge_attributes,
+            source,
+        )
+        .await
+        .expect("failed requesting map");
+        self.set_maps(maps, interests);
     }
     fn add_waypoints_to_interests(&mut self) {
         if let Some(waypoints) = &self.waypoints {
             self.interests
-                .extend(std::iter::repeat(0).zip(waypoints.iter().copied()));
+                .extend(waypoints.iter().map(|w| (0, *w, Vec::new())));
         }
+        self.interests.extend(
+            self.gpx_interests
+                .iter()
+                .map(|i| (GPX_WAYPOINT_INTEREST, *i, Vec::new())),
+        );
     }
     pub fn load_map<P: AsRef<std::path::Path>>(
         &mut self,
         map_name: P,
         key_values: &[(String, String)],
     ) -> std::io::Result<()> {
-        crate::load_maps_and_interests(&map_name, key_values, self.ski).map(|(maps, interests)| {
-            self.maps = maps;
-            self.interests = interests;
-            self.clip_maps();
-            if self.autodetect_waypoints {
-                self.detect_crossroads();
-            }
-            self.add_waypoints_to_interests();
-        })
+        crate::load_maps_and_interests(&map_name, key_values, self.profile, self.edge_attributes)
+            .map(|(maps, interests)| self.set_maps(maps, interests))
+    }
+    // build the maps from a .osm.pbf extract instead of asking overpass
+    #[cfg(feature = "pbf")]
+    pub fn load_pbf<P: AsRef<std::path::Path>>(
+        &mut self,
+        pbf_path: P,
+        key_values: &[(String, String)],
+    ) -> std::io::Result<()> {
+        crate::load_maps_and_interests_from_pbf(
+            pbf_path,
+            &self.map_polygons,
+            key_values,
+            self.profile,
+            self.edge_attributes,
+        )
+        .map(|(maps, interests)| self.set_maps(maps, interests))
+    }
+    fn set_maps(&mut self, maps: Vec<Map>, interests: Vec<(usize, Node, InterestDetails)>) {
+        self.maps = maps;
+        self.interests = interests;
+        self.clip_maps();
+        if self.autodetect_waypoints {
+            self.detect_crossroads();
+        }
+        self.add_waypoints_to_interests();
     }
     pub fn save_svg<P: AsRef<std::path::Path>>(&self, svg_path: P) -> std::io::Result<()> {
         let interests_nodes = UniColorNodes(
             self.interests
                 .iter()
                 .skip(1) // skip waypoints
-                .map(|(_, n)| n)
+                .map(|(_, n, _)| n)
                 .cloned()
                 .collect::<Vec<_>>(),
         );
@@ -303,7 +499,7 @@ impl Gps {
         for map in &mut self.maps {
             let side = map.side;
             let tiles_wanted = if let Some(gpx_path) = &self.path {
-                let path_map = Map::from_path(gpx_path.clone(), side);
+                let path_map = Map::from_path(gpx_path.clone(), &self.breaks, side);
                 path_map
                     .non_empty_tiles()
                     .map(|(x, y)| {
@@ -319,10 +515,11 @@ impl Gps {
                     })
                     .collect::<HashSet<(usize, usize)>>()
             } else {
-                let xmin = self.map_polygon[0].x;
-                let ymin = self.map_polygon[0].y;
-                let xmax = self.map_polygon[2].x;
-                let ymax = self.map_polygon[2].y;
+                // areas are rectangles
+                let xmin = self.map_polygons[0][0].x;
+                let ymin = self.map_polygons[0][0].y;
+                let xmax = self.map_polygons[0][2].x;
+                let ymax = self.map_polygons[0][2].y;
                 let width = xmax - xmin;
                 let height = ymax - ymin;
 
@@ -341,7 +538,7 @@ impl Gps {
                     .collect::<HashSet<_>>()
             };
             map.keep_tiles(&tiles_wanted);
-            self.interests.retain(|(_, p)| {
+            self.interests.retain(|(_, p, _)| {
                 let tile_x = ((p.x / side).floor() as isize - map.first_tile.0) as usize;
                 let tile_y = ((p.y / side).floor() as isize - map.first_tile.1) as usize;
                 tiles_wanted.contains(&(tile_x, tile_y))
@@ -350,25 +547,88 @@ impl Gps {
         }
     }
 
+    // informations displayed by the watch
+    fn metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = Vec::new();
+        if let Some(name) = &self.name {
+            metadata.push(("name".to_owned(), name.clone()));
+        }
+        if let Some(path) = &self.path {
+            metadata.push(("length".to_owned(), format!("{:.0}", path_length(path))));
+            if let Some(heights) = self.heights.as_ref().and_then(|h| path_heights(path, h)) {
+                metadata.push((
+                    "elevation_gain".to_owned(),
+                    format!("{:.0}", elevation_gain(&heights)),
+                ));
+            }
+        }
+        metadata.push(("created".to_owned(), creation_time()));
+        metadata.push((
+            "source".to_owned(),
+            format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+        ));
+        if !self.maps.is_empty() || !self.interests.is_empty() {
+            metadata.push(("attribution".to_owned(), OSM_ATTRIBUTION.to_owned()));
+        }
+        metadata
+    }
+
     pub fn write_gps<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let mut blocks = Vec::new();
+        buffer_block(&mut blocks, |w| save_metadata(&self.metadata(), w))?;
         eprintln!("saving interests");
         let side = self.maps[0].side;
-        save_tiled_interests(&self.interests, side, writer)?;
+        let interests = self
+            .interests
+            .iter()
+            .map(|(interest, node, _)| (*interest, *node))
+            .collect::<Vec<_>>();
+        buffer_block(&mut blocks, |w| save_tiled_interests(&interests, side, w))?;
+        buffer_block(&mut blocks, |w| {
+            save_interests_details(&self.interests, &self.interests_tags, side, w)
+        })?;
         if let Some(gpx_path) = &self.path {
             if let Some(waypoints) = &self.waypoints {
                 eprintln!("saving the path");
-                save_path(gpx_path, waypoints, writer)?;
+                buffer_block(&mut blocks, |w| {
+                    save_path(gpx_path, waypoints, &self.breaks, w)
+                })?;
+                // route layers only repeat ways of the first map
+                // but their labels also name these ways in the first map
+                let (turns_maps, route_labels) = if self.profile == Profile::Ski {
+                    (&self.maps[..], HashSet::new())
+                } else {
+                    let labels = self.maps[1..]
+                        .iter()
+                        .flat_map(|map| map.streets.keys().map(|label| label.as_str()))
+                        .collect();
+                    (&self.maps[..1], labels)
+                };
+                let instructions =
+                    path_instructions(gpx_path, waypoints, &self.breaks, turns_maps, &route_labels);
+                buffer_block(&mut blocks, |w| save_instructions(&instructions, w))?;
                 eprintln!("saving the pathtiles");
-                let path = Map::from_path(gpx_path.clone(), side);
-                path.save_tiles(writer)?;
+                let path = Map::from_path(gpx_path.clone(), &self.breaks, side);
+                buffer_block(&mut blocks, |w| path.save_tiles(w, self.compressed_tiles))?;
             }
             if let Some(heights) = &self.heights {
-                save_heights(gpx_path, heights, writer)?;
+                buffer_block(&mut blocks, |w| {
+                    save_heights(gpx_path, heights, self.precise_heights, w)
+                })?;
+            }
+            if let Some(times) = &self.times {
+                buffer_block(&mut blocks, |w| save_times(gpx_path, times, w))?;
             }
         }
         eprintln!("saving the maptiles");
         for map in &self.maps {
-            map.save_tiles(writer)?;
+            buffer_block(&mut blocks, |w| map.save_tiles(w, self.compressed_tiles))?;
+            buffer_block(&mut blocks, |w| map.save_streets(w))?;
+        }
+
+        save_gps_header(blocks.len(), writer)?;
+        for block in &blocks {
+            writer.write_all(block)?;
         }
         eprintln!("all is saved");
 
@@ -376,7 +636,23 @@ impl Gps {
     }
 }
 
-fn inflate_polyline(rp: &[Node], side: f64) -> Vec<Node> {
+// blocks are kept in memory until all are saved since the file header
+// needs their number.
+fn buffer_block<F: FnOnce(&mut Vec<u8>) -> std::io::Result<()>>(
+    blocks: &mut Vec<Vec<u8>>,
+    save: F,
+) -> std::io::Result<()> {
+    let mut block = Vec::new();
+    save(&mut block)?;
+    if !block.is_empty() {
+        blocks.push(block);
+    }
+    Ok(())
+}
+
+// polygons around the tiles crossed by the polyline segments, usually a single one.
+// segments far from each other get separate polygons.
+fn inflate_polyline(rp: &[Node], breaks: &[usize], side: f64) -> Vec<Vec<Node>> {
     fn segment_tiles<'a>(
         p1: &'a Node,
         p2: &'a Node,
@@ -410,8 +686,18 @@ fn inflate_polyline(rp: &[Node], side: f64) -> Vec<Node> {
     }
     //find all tiles containing path
     let mut tiles = HashSet::new();
-    for (p1, p2) in rp.iter().tuple_windows() {
-        tiles.extend(segment_tiles(p1, p2, side));
+    for segment in segments_ranges(rp.len(), breaks) {
+        let points = &rp[segment];
+        if let [point] = points {
+            let (x, y) = (
+                (point.x / side).floor() as i32,
+                (point.y / side).floor() as i32,
+            );
+            tiles.extend(itertools::iproduct!(x - 1..x + 2, y - 1..y + 2));
+        }
+        for (p1, p2) in points.iter().tuple_windows() {
+            tiles.extend(segment_tiles(p1, p2, side));
+        }
     }
     // find border around these tiles
     let mut segments = HashMap::new();
@@ -429,20 +715,29 @@ fn inflate_polyline(rp: &[Node], side: f64) -> Vec<Node> {
             segments.insert((tile_x + 1, tile_y), (tile_x, tile_y));
         }
     }
-    // now rebuild the polygon
-    let mut polygon = Vec::new();
-    // start with leftmost point to avoid moving on possible inner holes
-    let start_point = segments.keys().min_by_key(|p| p.0).unwrap();
-    let mut current_tx = start_point.0;
-    let mut current_ty = start_point.1;
-    loop {
-        polygon.push([current_tx, current_ty]);
-        let next_point = segments.get(&(current_tx, current_ty)).unwrap();
-        if next_point == start_point {
-            // now simplify a bit the polygon and build nodes
-            return polygon
+    // now rebuild the polygons, one border after the other
+    let mut polygons = Vec::new();
+    while let Some(start_point) = segments.keys().min().copied() {
+        let mut polygon = Vec::new();
+        let mut current_point = start_point;
+        while let Some(next_point) = segments.remove(&current_point) {
+            polygon.push([current_point.0, current_point.1]);
+            current_point = next_point;
+        }
+        // outer borders go clockwise while borders of inner holes go counterclockwise
+        let area: i64 = polygon
+            .iter()
+            .zip(polygon.iter().cycle().skip(1))
+            .map(|([x1, y1], [x2, y2])| *x1 as i64 * *y2 as i64 - *x2 as i64 * *y1 as i64)
+            .sum();
+        if current_point != start_point || area >= 0 {
+            continue;
+        }
+        // now simplify a bit the polygon and build nodes
+        polygons.push(
+            polygon
                 .iter()
-                .tuple_windows()
+                .circular_tuple_windows()
                 .filter_map(|([x1, y1], [x2, y2], [x3, y3])| {
                     if (x1 == x2 && x2 == x3) || (y1 == y2 && y2 == y3) {
                         None
@@ -450,30 +745,46 @@ fn inflate_polyline(rp: &[Node], side: f64) -> Vec<Node> {
                         Some(Node::new(*x2 as f64 * side, *y2 as f64 * side))
                     }
                 })
-                .collect();
-        }
-        current_tx = next_point.0;
-        current_ty = next_point.1;
+                .collect(),
+        );
     }
+    polygons
 }
 
-pub fn simplify_path_around_waypoints(p: &Vec<Node>, waypoints: &HashSet<Node>) -> Vec<Node> {
+// indices of the points kept when simplifying the path between waypoints.
+// we cannot simplify across segments so each one is simplified alone.
+fn simplified_path_indices(p: &[Node], waypoints: &HashSet<Node>, breaks: &[usize]) -> Vec<usize> {
     println!("we have {} waypoints", waypoints.len());
 
     println!("initially we had {} points", p.len());
 
     // simplify path
-    let mut rp = Vec::new();
-    let mut segment = Vec::new();
-    for point in p {
-        segment.push(*point);
-        if waypoints.contains(point) && segment.len() >= 2 {
-            let mut s = simplify_path(&segment, 0.00015);
-            rp.append(&mut s);
-            segment = rp.pop().into_iter().collect();
+    let mut kept = Vec::new();
+    for segment in segments_ranges(p.len(), breaks) {
+        let mut start = segment.start;
+        for end in (segment.start + 1)..segment.end {
+            if waypoints.contains(&p[end]) || end + 1 == segment.end {
+                let simplified = simplify_path_indices(&p[start..=end], 0.00015);
+                // the segment end starts the next segment
+                kept.extend(simplified[..simplified.len() - 1].iter().map(|i| start + i));
+                start = end;
+            }
         }
+        kept.push(start);
     }
-    rp.append(&mut segment);
-    println!("we now have {} points", rp.len());
-    rp
+    println!("we now have {} points", kept.len());
+    kept
+}
+
+// breaks indices once only the kept points remain.
+// segments starts are always kept.
+fn kept_breaks(breaks: &[usize], kept: &[usize]) -> Vec<usize> {
+    breaks
+        .iter()
+        .filter_map(|b| kept.binary_search(b).ok())
+        .collect()
+}
+
+fn keep_indices<T: Copy>(values: &[T], kept: &[usize]) -> Vec<T> {
+    kept.iter().map(|&i| values[i]).collect()
 }
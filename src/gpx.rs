@@ -3,45 +3,127 @@ use std::{
     io::{Read, Write},
 };
 
+use byteorder::{LittleEndian, ReadBytesExt};
 use gpx::{read, Gpx};
 use itertools::Itertools;
 
-use crate::{maps_and_interests_from_string, request, Map, Node};
+use crate::{
+    decoder::DecodeError,
+    interests::InterestDetails,
+    map::{write_block, BlockType},
+    maps_and_interests_from_string, request,
+    utils::{read_bytes, read_varint, write_varint},
+    Map, Node, OsmSource, Profile,
+};
 
 const LOWER_SHARP_TURN: f64 = 80.0 * std::f64::consts::PI / 180.0;
 const UPPER_SHARP_TURN: f64 = std::f64::consts::PI * 2.0 - LOWER_SHARP_TURN;
 
-pub fn parse_gpx_points<R: Read>(reader: R) -> (HashSet<Node>, Vec<Node>, HashMap<Node, f64>) {
+/// Everything we take from a gpx file.
+pub struct GpxContent {
+    /// all tracks, routes and segments, one after the other
+    pub points: Vec<Node>,
+    /// commented points and top-level waypoints on the path
+    pub waypoints: HashSet<Node>,
+    /// indices of the points starting a new segment :
+    /// the path is interrupted just before them
+    pub breaks: Vec<usize>,
+    pub heights: HashMap<Node, f64>,
+    /// in seconds since epoch, one entry per point
+    /// (a point can be visited several times on a loop)
+    pub times: Vec<Option<f64>>,
+    pub name: Option<String>,
+    /// top-level waypoints away from the path (saved as `GPX_WAYPOINT_INTEREST` interests)
+    pub interests: Vec<Node>,
+}
+
+// top-level waypoints closer than this (in degrees) to a path point become path waypoints
+const WAYPOINT_ON_PATH_DISTANCE: f64 = 0.0001;
+
+pub fn parse_gpx_points<R: Read>(reader: R) -> std::io::Result<GpxContent> {
     // read takes any io::Read and gives a Result<Gpx, Error>.
-    let mut gpx: Gpx = read(reader).unwrap();
-    eprintln!("we have {} tracks", gpx.tracks.len());
+    let gpx: Gpx =
+        read(reader).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    eprintln!(
+        "we have {} tracks, {} routes and {} waypoints",
+        gpx.tracks.len(),
+        gpx.routes.len(),
+        gpx.waypoints.len()
+    );
 
-    let mut waypoints = HashSet::new();
-    let mut heights = HashMap::new();
+    let mut content = GpxContent {
+        points: Vec::new(),
+        waypoints: HashSet::new(),
+        breaks: Vec::new(),
+        heights: HashMap::new(),
+        times: Vec::new(),
+        name: gpx.metadata.and_then(|metadata| metadata.name),
+        interests: Vec::new(),
+    };
+    content.name = content.name.or_else(|| {
+        gpx.tracks
+            .iter()
+            .find_map(|track| track.name.clone())
+            .or_else(|| gpx.routes.iter().find_map(|route| route.name.clone()))
+    });
 
-    let points = gpx
+    let segments = gpx
         .tracks
-        .pop()
-        .unwrap()
-        .segments
         .into_iter()
-        .flat_map(|segment| segment.points.into_iter())
-        .map(|p| {
-            let is_commented = p.comment.is_some();
-
+        .flat_map(|track| track.segments.into_iter().map(|segment| segment.points))
+        .chain(gpx.routes.into_iter().map(|route| route.points))
+        .filter(|points| !points.is_empty());
+    for segment in segments {
+        for (i, p) in segment.into_iter().enumerate() {
             let (x, y) = p.point().x_y();
             let n = Node { x, y };
-            if is_commented {
-                waypoints.insert(n);
+            if i == 0 && !content.points.is_empty() {
+                content.breaks.push(content.points.len());
+            }
+            if p.comment.is_some() {
+                content.waypoints.insert(n);
             }
-
             if let Some(height) = p.elevation {
-                heights.insert(n, height);
+                content.heights.insert(n, height);
             }
-            n
-        })
-        .collect::<Vec<_>>();
-    (waypoints, points, heights)
+            content.times.push(
+                p.time.map(|time| {
+                    time::OffsetDateTime::from(time).unix_timestamp_nanos() as f64 / 1e9
+                }),
+            );
+            content.points.push(n);
+        }
+    }
+
+    for waypoint in gpx.waypoints {
+        let (x, y) = waypoint.point().x_y();
+        let n = Node { x, y };
+        let nearest_point = content
+            .points
+            .iter()
+            .min_by(|p1, p2| {
+                p1.squared_distance_to(&n)
+                    .partial_cmp(&p2.squared_distance_to(&n))
+                    .unwrap()
+            })
+            .filter(|p| p.distance_to(&n) <= WAYPOINT_ON_PATH_DISTANCE);
+        if let Some(point) = nearest_point {
+            content.waypoints.insert(*point);
+        } else {
+            content.interests.push(n);
+        }
+    }
+    Ok(content)
+}
+
+// ranges of points indices of each segment
+pub fn segments_ranges(points_number: usize, breaks: &[usize]) -> Vec<std::ops::Range<usize>> {
+    std::iter::once(0)
+        .chain(breaks.iter().copied())
+        .chain(std::iter::once(points_number))
+        .tuple_windows()
+        .map(|(start, end)| start..end)
+        .collect()
 }
 
 pub fn detect_sharp_turns(path: &[Node], waypoints: &mut HashSet<Node>) {
@@ -75,14 +157,16 @@ pub fn detect_sharp_turns(path: &[Node], waypoints: &mut HashSet<Node>) {
         });
 }
 
-pub async fn request_maps_from<P: AsRef<std::path::Path>>(
-    polygon: &[Node],
+pub async fn request_maps_from<P: AsRef<std::path::Path>, S: OsmSource>(
+    polygons: &[Vec<Node>],
     key_values: &[(String, String)],
     map_name: Option<P>,
-    ski: bool,
-) -> Result<(Vec<Map>, Vec<(usize, Node)>), Box<dyn std::error::Error>> {
+    profile: Profile,
+    edge_attributes: bool,
+    source: &S,
+) -> Result<(Vec<Map>, Vec<(usize, Node, InterestDetails)>), Box<dyn std::error::Error>> {
     crate::log("requesting map");
-    let osm_answer = request(polygon, ski).await?;
+    let osm_answer = request(polygons, profile, key_values, source).await?;
     crate::log("got the request answer");
     eprintln!("we got the map, saving it");
     if let Some(map_name) = map_name {
@@ -90,59 +174,275 @@ pub async fn request_maps_from<P: AsRef<std::path::Path>>(
         writer.write_all(osm_answer.as_bytes())?;
         eprintln!("we saved the map");
     }
-    let side = if ski {
-        1. / 150.
-    } else {
-        crate::map::DEFAULT_SIDE
-    };
     Ok(maps_and_interests_from_string(
         &osm_answer,
         key_values,
-        ski,
-        side,
-    ))
+        profile,
+        edge_attributes,
+        crate::map::tiles_side(profile),
+    )?)
+}
+
+/// Heights of all path points, missing ones being interpolated
+/// along the path distance between the nearest known heights.
+/// Returns None if no point has a height.
+pub fn path_heights(points: &[Node], heights: &HashMap<Node, f64>) -> Option<Vec<f64>> {
+    let heights = points
+        .iter()
+        .map(|p| heights.get(p).copied())
+        .collect::<Vec<_>>();
+    interpolate_along_path(points, &heights)
+}
+
+/// Elapsed seconds at each path point since the start,
+/// interpolated like heights for points without time.
+/// `times` has one entry per point.
+/// Returns None if no point has a time.
+pub fn path_times(points: &[Node], times: &[Option<f64>]) -> Option<Vec<u32>> {
+    let times = interpolate_along_path(points, times)?;
+    let start = times[0];
+    // gpx clocks can jump backwards, elapsed times never do
+    Some(
+        times
+            .iter()
+            .scan(0, |elapsed, time| {
+                *elapsed = (*elapsed).max((time - start).round().max(0.) as u32);
+                Some(*elapsed)
+            })
+            .collect(),
+    )
+}
+
+// values of all path points, missing ones being interpolated
+// along the path distance between the nearest known values.
+// there is one value per point.
+fn interpolate_along_path(points: &[Node], values: &[Option<f64>]) -> Option<Vec<f64>> {
+    let distances = std::iter::once(0.)
+        .chain(
+            points
+                .iter()
+                .tuple_windows()
+                .scan(0., |distance, (p1, p2)| {
+                    *distance += p1.exact_meters_to(p2);
+                    Some(*distance)
+                }),
+        )
+        .collect::<Vec<f64>>();
+    let known_values = values
+        .iter()
+        .zip(&distances)
+        .filter_map(|(v, d)| v.map(|v| (*d, v)))
+        .collect::<Vec<_>>();
+    if known_values.is_empty() {
+        return None;
+    }
+    Some(
+        distances
+            .iter()
+            .map(|d| {
+                let next = known_values.partition_point(|(known_d, _)| known_d < d);
+                match (
+                    next.checked_sub(1).map(|i| &known_values[i]),
+                    known_values.get(next),
+                ) {
+                    (Some((d1, v1)), Some((d2, v2))) if d2 > d1 => {
+                        v1 + (v2 - v1) * (d - d1) / (d2 - d1)
+                    }
+                    (_, Some((_, v))) | (Some((_, v)), None) => *v,
+                    (None, None) => unreachable!(),
+                }
+            })
+            .collect(),
+    )
 }
 
-/// save heights for path points.
+/// save heights for path points, in meters or in decimeters.
 /// this must be called after saving the path so that
 /// the parser knows how many heights we have.
 pub fn save_heights<W: Write>(
     points: &[Node],
     heights: &HashMap<Node, f64>,
+    decimeters: bool,
     writer: &mut W,
 ) -> std::io::Result<()> {
-    if points.iter().any(|p| heights.get(p).is_none()) {
-        return Ok(()); // abort if even one height is missing
-    }
+    let Some(heights) = path_heights(points, heights) else {
+        return Ok(()); // no heights at all
+    };
     eprintln!("saving heights");
-    writer.write_all(&[crate::map::BlockType::Heights as u8])?;
-    for point in points {
-        writer.write_all(&(heights[point] as i16).to_le_bytes())?;
+    write_block(writer, BlockType::Heights, |writer| {
+        if decimeters {
+            // varints of differences between successive heights
+            writer.write_all(&[HEIGHTS_IN_DECIMETERS])?;
+            let mut previous = 0;
+            for height in heights {
+                let height = (height * 10.).round() as i64;
+                write_varint(height - previous, writer)?;
+                previous = height;
+            }
+        } else {
+            writer.write_all(&[HEIGHTS_IN_METERS])?;
+            for height in heights {
+                writer.write_all(&(height.round() as i16).to_le_bytes())?;
+            }
+        }
+        Ok(())
+    })
+}
+
+const HEIGHTS_IN_METERS: u8 = 0;
+const HEIGHTS_IN_DECIMETERS: u8 = 1;
+
+/// load back heights saved by `save_heights`.
+/// we need the path length to know how many heights there are.
+pub fn load_heights<R: Read>(
+    points_number: usize,
+    reader: &mut R,
+) -> Result<Vec<f64>, DecodeError> {
+    match reader.read_u8()? {
+        HEIGHTS_IN_METERS => (0..points_number)
+            .map(|_| Ok(reader.read_i16::<LittleEndian>()? as f64))
+            .collect(),
+        HEIGHTS_IN_DECIMETERS => {
+            let mut height = 0i64;
+            (0..points_number)
+                .map(|_| {
+                    height = height
+                        .checked_add(read_varint(reader)?)
+                        .ok_or_else(|| DecodeError::InvalidData("invalid height".to_owned()))?;
+                    Ok(height as f64 / 10.)
+                })
+                .collect()
+        }
+        encoding => Err(DecodeError::InvalidData(format!(
+            "unknown heights encoding {encoding}"
+        ))),
     }
-    Ok(())
 }
 
+/// save elapsed seconds for path points
+/// (varints of differences between successive times).
+/// like heights, this must be called after saving the path.
+pub fn save_times<W: Write>(
+    points: &[Node],
+    times: &[Option<f64>],
+    writer: &mut W,
+) -> std::io::Result<()> {
+    let Some(times) = path_times(points, times) else {
+        return Ok(()); // no times at all
+    };
+    eprintln!("saving times");
+    write_block(writer, BlockType::Times, |writer| {
+        let mut previous = 0;
+        for time in times {
+            write_varint((time - previous) as i64, writer)?;
+            previous = time;
+        }
+        Ok(())
+    })
+}
+
+/// load back times saved by `save_times`.
+pub fn load_times<R: Read>(points_number: usize, reader: &mut R) -> Result<Vec<u32>, DecodeError> {
+    let mut time = 0u32;
+    (0..points_number)
+        .map(|_| {
+            time = i64::from(time)
+                .checked_add(read_varint(reader)?)
+                .and_then(|t| u32::try_from(t).ok())
+                .ok_or_else(|| DecodeError::InvalidData("invalid time".to_owned()))?;
+            Ok(time)
+        })
+        .collect()
+}
+
+/// Path coordinates are stored as multiples of this (in degrees)
+/// so the error on each coordinate is at most half of it (about 5cm).
+pub const PATH_RESOLUTION: f64 = 1e-6;
+
+/// save the path, its waypoints bitmap and the indices of the points
+/// starting a new segment.
+/// coordinates are rounded to `PATH_RESOLUTION` and each one is stored
+/// as a varint of its difference with the previous one.
 pub fn save_path<W: Write>(
     points: &[Node],
     waypoints: &HashSet<Node>,
+    breaks: &[usize],
     writer: &mut W,
 ) -> std::io::Result<()> {
-    writer.write_all(&[crate::map::BlockType::Path as u8])?;
-    writer.write_all(&(points.len() as u16).to_le_bytes())?;
-    for coordinates in points.iter().flat_map(|p| [p.x, p.y]) {
-        writer.write_all(&coordinates.to_le_bytes())?;
-    }
+    write_block(writer, BlockType::Path, |writer| {
+        writer.write_all(&(points.len() as u32).to_le_bytes())?;
+        writer.write_all(&PATH_RESOLUTION.to_le_bytes())?;
+        let mut previous = [0i64; 2];
+        for point in points {
+            for (coordinate, previous) in [point.x, point.y].iter().zip(&mut previous) {
+                let fixed = (coordinate / PATH_RESOLUTION).round() as i64;
+                write_varint(fixed - *previous, writer)?;
+                *previous = fixed;
+            }
+        }
 
-    let mut waypoints_bits = std::iter::repeat(0u8)
-        .take(points.len() / 8 + if points.len() % 8 != 0 { 1 } else { 0 })
-        .collect::<Vec<u8>>();
-    points.iter().enumerate().for_each(|(i, p)| {
-        if waypoints.contains(p) {
-            waypoints_bits[i / 8] |= 1 << (i % 8)
+        let mut waypoints_bits = vec![0u8; points.len().div_ceil(8)];
+        points.iter().enumerate().for_each(|(i, p)| {
+            if waypoints.contains(p) {
+                waypoints_bits[i / 8] |= 1 << (i % 8)
+            }
+        });
+        writer.write_all(&waypoints_bits)?;
+
+        // breaks are rare, store them as varints of differences
+        write_varint(breaks.len() as i64, writer)?;
+        let mut previous = 0;
+        for point in breaks {
+            write_varint((point - previous) as i64, writer)?;
+            previous = *point;
         }
-    });
-    for byte in &waypoints_bits {
-        writer.write_all(&byte.to_le_bytes())?;
-    }
-    Ok(())
+        Ok(())
+    })
+}
+
+/// load back a path saved by `save_path` (block type and size already read).
+/// returns points, waypoints and breaks.
+#[allow(clippy::type_complexity)]
+pub fn load_path<R: Read>(
+    reader: &mut R,
+) -> Result<(Vec<Node>, HashSet<Node>, Vec<usize>), DecodeError> {
+    let points_number = reader.read_u32::<LittleEndian>()? as usize;
+    let resolution = reader.read_f64::<LittleEndian>()?;
+    let mut fixed = [0i64; 2];
+    let points = (0..points_number)
+        .map(|_| {
+            for coordinate in &mut fixed {
+                *coordinate = coordinate
+                    .checked_add(read_varint(reader)?)
+                    .ok_or_else(|| DecodeError::InvalidData("invalid path point".to_owned()))?;
+            }
+            Ok(Node::new(
+                fixed[0] as f64 * resolution,
+                fixed[1] as f64 * resolution,
+            ))
+        })
+        .collect::<Result<Vec<_>, DecodeError>>()?;
+
+    let waypoints_bits = read_bytes(reader, points_number.div_ceil(8))?;
+    let waypoints = points
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| waypoints_bits[i / 8] & (1 << (i % 8)) != 0)
+        .map(|(_, p)| *p)
+        .collect();
+
+    let breaks_number = read_varint(reader)?;
+    let mut point = 0i64;
+    let breaks = (0..breaks_number)
+        .map(|_| {
+            // breaks are strictly increasing
+            point = Some(read_varint(reader)?)
+                .filter(|delta| *delta > 0)
+                .and_then(|delta| point.checked_add(delta))
+                .filter(|p| *p < points_number as i64)
+                .ok_or_else(|| DecodeError::BadSize("invalid path break".to_owned()))?;
+            Ok(point as usize)
+        })
+        .collect::<Result<Vec<_>, DecodeError>>()?;
+    Ok((points, waypoints, breaks))
 }
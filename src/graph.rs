@@ -190,7 +190,14 @@ impl Map {
         }
     }
 
-    pub fn detect_crossroads(&self, path: &mut Vec<Node>, waypoints: &mut HashSet<Node>) {
+    // adds non obvious crossroads on the path to the waypoints.
+    // there is no turn where the path is interrupted (breaks start new segments).
+    pub fn detect_crossroads(
+        &self,
+        path: &[Node],
+        waypoints: &mut HashSet<Node>,
+        breaks: &[usize],
+    ) {
         eprintln!("detecting crossroads");
         // let rp = crate::gps::simplify_path_around_waypoints(&path, &waypoints);
 
@@ -218,31 +225,29 @@ impl Map {
 
         let mut current_distance = 0.;
         let mut previous_waypoint_distance = None;
-        for (previous_node, node, next_node) in path.iter().tuple_windows() {
+        for ((_, previous_node), (index, node), (_, next_node)) in
+            path.iter().enumerate().tuple_windows()
+        {
             current_distance += previous_node.distance_to(node);
+            if breaks.contains(&index) || breaks.contains(&(index + 1)) {
+                continue;
+            }
             if self
                 .node_tiles(node)
                 .flat_map(|(tile_x, tile_y)| self.tile_edges(tile_x, tile_y))
                 .flatten()
                 .filter(|n| n.distance_to(node) <= 0.0001)
                 .any(|n| crossroads.contains(&n))
-            {
-                if !(self.obvious_crossroad(node, previous_node, next_node)
+                && !(self.obvious_crossroad(node, previous_node, next_node)
                     && self.obvious_crossroad(node, next_node, previous_node))
-                {
-                    if previous_waypoint_distance
-                        .map(|pd| current_distance - pd > 0.0003)
-                        .unwrap_or(true)
-                    {
-                        waypoints.insert(*node);
-                        previous_waypoint_distance = Some(current_distance);
-                    }
-                }
+                && previous_waypoint_distance
+                    .map(|pd| current_distance - pd > 0.0003)
+                    .unwrap_or(true)
+            {
+                waypoints.insert(*node);
+                previous_waypoint_distance = Some(current_distance);
             }
         }
-        let final_path = crate::gps::simplify_path_around_waypoints(path, waypoints);
-        *path = final_path;
-        return;
 
         // let tiled_segments = self.hash_segments_on_tiles(&rp);
         // for possible_waypoint in crossroads {
@@ -316,6 +321,7 @@ impl Map {
         // }
     }
 
+    #[allow(dead_code)]
     fn hash_segments_on_tiles(&self, path: &[Node]) -> HashMap<(usize, usize), HashSet<usize>> {
         // figure out for each tile which path segments are on it
         let mut segments_tiles: HashMap<(usize, usize), HashSet<usize>> = HashMap::new();
@@ -323,17 +329,17 @@ impl Map {
             let mut new_nodes = std::iter::once(*n1)
                 .chain(
                     grid_coordinates_between(n1.x, n2.x, self.side)
-                        .map(|x| n1.vertical_segment_intersection(&n2, x))
+                        .map(|x| n1.vertical_segment_intersection(n2, x))
                         .chain(
                             grid_coordinates_between(n1.y, n2.y, self.side)
-                                .map(|y| n1.horizontal_segment_intersection(&n2, y)),
+                                .map(|y| n1.horizontal_segment_intersection(n2, y)),
                         ),
                 )
                 .chain(std::iter::once(*n2))
                 .collect::<Vec<_>>();
             new_nodes.sort_unstable_by(|na, nb| {
-                let da = na.squared_distance_to(&n1);
-                let db = nb.squared_distance_to(&n1);
+                let da = na.squared_distance_to(n1);
+                let db = nb.squared_distance_to(n1);
                 da.partial_cmp(&db).unwrap()
             });
             new_nodes
@@ -346,6 +352,7 @@ impl Map {
         segments_tiles
     }
 
+    #[allow(dead_code)]
     fn nearby_high_degree_node(&self, node: &Node, treshold: f64) -> bool {
         self.node_tiles(node)
             .flat_map(|(tile_x, tile_y)| self.tile_edges(tile_x, tile_y))
@@ -463,13 +470,9 @@ impl Map {
     }
 
     fn tile_edges(&self, tile_x: usize, tile_y: usize) -> impl Iterator<Item = [GNode; 2]> + '_ {
-        let tile_number = (tile_x + tile_y * self.grid_size.0 as usize) as u16;
-        (0..(self.tile_ways_number(tile_number))).map(move |local_way_id| {
-            self.way(CWayId {
-                tile_number,
-                local_way_id,
-            })
-        })
+        let tile_number = (tile_x + tile_y * self.grid_size.0) as u32;
+        self.tile_ways_ids(tile_number)
+            .map(move |way_id| self.way(way_id))
     }
 
     fn find_starting_node(&self, gps_start: &Node) -> GNode {
@@ -515,15 +518,8 @@ impl Map {
     }
 
     fn way(&self, way_id: CWayId) -> [GNode; 2] {
-        let id1 = CNodeId {
-            tile_number: way_id.tile_number,
-            local_node_id: 2 * way_id.local_way_id as u16,
-        };
-
-        let id2 = CNodeId {
-            tile_number: way_id.tile_number,
-            local_node_id: 2 * way_id.local_way_id as u16 + 1,
-        };
+        let id1 = way_id.node(0);
+        let id2 = way_id.node(1);
         [
             GNode {
                 node: self.decode_node(id1),
@@ -593,14 +589,15 @@ impl PartialEq for HeapEntry {
 impl Eq for HeapEntry {}
 impl PartialOrd for HeapEntry {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.distance
-            .partial_cmp(&other.distance)
-            .map(std::cmp::Ordering::reverse)
+        Some(self.cmp(other))
     }
 }
 impl Ord for HeapEntry {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap()
+        self.distance
+            .partial_cmp(&other.distance)
+            .map(std::cmp::Ordering::reverse)
+            .unwrap()
     }
 }
 
@@ -0,0 +1,262 @@
+// turn by turn instructions at each waypoint of the path.
+use std::{
+    collections::{HashMap, HashSet},
+    f64::consts::PI,
+    io::{Read, Write},
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use itertools::Itertools;
+
+use crate::{
+    decoder::DecodeError,
+    map::{write_block, BlockType},
+    CWayId, Map, Node,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TurnDirection {
+    Straight,
+    SlightLeft,
+    Left,
+    SharpLeft,
+    SlightRight,
+    Right,
+    SharpRight,
+    UTurn,
+}
+
+impl TurnDirection {
+    // angle is in degrees, positive to the left
+    fn from_angle(angle: f64) -> Self {
+        let left = angle > 0.;
+        match angle.abs() {
+            a if a < 20. => TurnDirection::Straight,
+            a if a < 60. && left => TurnDirection::SlightLeft,
+            a if a < 60. => TurnDirection::SlightRight,
+            a if a < 120. && left => TurnDirection::Left,
+            a if a < 120. => TurnDirection::Right,
+            a if a < 170. && left => TurnDirection::SharpLeft,
+            a if a < 170. => TurnDirection::SharpRight,
+            _ => TurnDirection::UTurn,
+        }
+    }
+}
+
+impl TryFrom<u8> for TurnDirection {
+    type Error = DecodeError;
+
+    fn try_from(tag: u8) -> Result<Self, DecodeError> {
+        match tag {
+            0 => Ok(TurnDirection::Straight),
+            1 => Ok(TurnDirection::SlightLeft),
+            2 => Ok(TurnDirection::Left),
+            3 => Ok(TurnDirection::SharpLeft),
+            4 => Ok(TurnDirection::SlightRight),
+            5 => Ok(TurnDirection::Right),
+            6 => Ok(TurnDirection::SharpRight),
+            7 => Ok(TurnDirection::UTurn),
+            _ => Err(DecodeError::InvalidData(format!(
+                "invalid turn direction {tag}"
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    // index of the waypoint in the path
+    pub point: usize,
+    pub direction: TurnDirection,
+    // in degrees, positive to the left
+    pub angle: i16,
+    // branch to take among the leaving edges, counted from the rightmost one
+    pub branch: u8,
+    pub branches: u8,
+    // in meters, along the path (to the end for the last instruction)
+    pub distance_to_next: u32,
+    // name of the street we enter
+    pub street: Option<String>,
+}
+
+// turn angle (in radians, positive to the left) when going from given angle to the other
+fn turn_angle(from: f64, to: f64) -> f64 {
+    (to - from + 3. * PI).rem_euclid(2. * PI) - PI
+}
+
+// other end and street name of all edges touching given node
+fn leaving_edges<'a>(
+    map: &Map,
+    node: &Node,
+    streets_ids: &HashMap<CWayId, &'a str>,
+) -> Vec<(Node, Option<&'a str>)> {
+    map.node_tiles(node)
+        .flat_map(|(tile_x, tile_y)| map.tile_ways((tile_x + tile_y * map.grid_size.0) as u32))
+        .filter_map(|(way_id, [n1, n2], _)| {
+            let street = streets_ids.get(&way_id).copied();
+            let close_1 = n1.distance_to(node) <= 0.0001;
+            let close_2 = n2.distance_to(node) <= 0.0001;
+            if close_1 && !close_2 {
+                Some((n2, street))
+            } else if close_2 && !close_1 {
+                Some((n1, street))
+            } else {
+                None
+            }
+        })
+        .unique_by(|(destination, _)| *destination)
+        .collect()
+}
+
+// one instruction for each waypoint strictly inside a segment of the path
+// (there is no turn to take where the path is interrupted).
+// route labels are not streets names.
+pub fn path_instructions(
+    path: &[Node],
+    waypoints: &HashSet<Node>,
+    breaks: &[usize],
+    maps: &[Map],
+    route_labels: &HashSet<&str>,
+) -> Vec<Instruction> {
+    let maps_streets_ids = maps
+        .iter()
+        .map(|map| {
+            map.streets
+                .iter()
+                .filter(|(name, _)| !route_labels.contains(name.as_str()))
+                .flat_map(|(name, ways)| ways.iter().map(move |way_id| (*way_id, name.as_str())))
+                .collect::<HashMap<_, _>>()
+        })
+        .collect::<Vec<_>>();
+
+    let distances = std::iter::once(0.)
+        .chain(path.iter().tuple_windows().scan(0., |distance, (p1, p2)| {
+            *distance += p1.exact_meters_to(p2);
+            Some(*distance)
+        }))
+        .collect::<Vec<f64>>();
+
+    let mut instructions = path
+        .iter()
+        .enumerate()
+        .tuple_windows()
+        .filter(|(_, (point, node), _)| {
+            waypoints.contains(node) && !breaks.contains(point) && !breaks.contains(&(point + 1))
+        })
+        .map(|((_, previous_node), (point, node), (_, next_node))| {
+            let arriving_angle = previous_node.angle_to(node);
+            let leaving_angle = node.angle_to(next_node);
+            let angle = turn_angle(arriving_angle, leaving_angle).to_degrees();
+
+            // sort branches from right to left, not counting the one we arrive on
+            let mut branches = maps
+                .iter()
+                .zip(&maps_streets_ids)
+                .flat_map(|(map, streets_ids)| leaving_edges(map, node, streets_ids))
+                // maps can share ways, keep the first one
+                .unique_by(|(destination, _)| *destination)
+                .map(|(destination, street)| {
+                    (
+                        turn_angle(arriving_angle, node.angle_to(&destination)),
+                        street,
+                    )
+                })
+                .collect::<Vec<_>>();
+            branches.sort_by(|(a1, _), (a2, _)| a1.partial_cmp(a2).unwrap());
+            if branches.len() > 1 {
+                // we arrive from the most backward branch
+                let arriving_branch = branches
+                    .iter()
+                    .position_max_by(|(a1, _), (a2, _)| a1.abs().partial_cmp(&a2.abs()).unwrap())
+                    .unwrap();
+                branches.remove(arriving_branch);
+            }
+            let taken_branch = branches.iter().position_min_by(|(a1, _), (a2, _)| {
+                (a1 - angle.to_radians())
+                    .abs()
+                    .partial_cmp(&(a2 - angle.to_radians()).abs())
+                    .unwrap()
+            });
+
+            Instruction {
+                point,
+                direction: TurnDirection::from_angle(angle),
+                angle: angle.round() as i16,
+                branch: taken_branch.unwrap_or_default() as u8,
+                branches: branches.len() as u8,
+                distance_to_next: 0,
+                street: taken_branch
+                    .and_then(|b| branches[b].1)
+                    .map(|name| name.to_owned()),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let ends = instructions
+        .iter()
+        .skip(1)
+        .map(|next| distances[next.point])
+        .chain(distances.last().copied())
+        .collect::<Vec<_>>();
+    for (instruction, end) in instructions.iter_mut().zip(ends) {
+        instruction.distance_to_next = (end - distances[instruction.point]).round() as u32;
+    }
+    instructions
+}
+
+// fixed size records followed by the street name (its length in bytes first)
+pub fn save_instructions<W: Write>(
+    instructions: &[Instruction],
+    writer: &mut W,
+) -> std::io::Result<()> {
+    if instructions.is_empty() {
+        return Ok(());
+    }
+    write_block(writer, BlockType::Instructions, |writer| {
+        writer.write_all(&(instructions.len() as u32).to_le_bytes())?;
+        for instruction in instructions {
+            writer.write_all(&(instruction.point as u32).to_le_bytes())?;
+            writer.write_all(&[instruction.direction as u8])?;
+            writer.write_all(&instruction.angle.to_le_bytes())?;
+            writer.write_all(&[instruction.branch, instruction.branches])?;
+            writer.write_all(&instruction.distance_to_next.to_le_bytes())?;
+            let street = crate::streets::street_label(instruction.street.as_deref().unwrap_or(""));
+            // cut long names on a char boundary
+            let mut length = street.len().min(u8::MAX as usize);
+            while !street.is_char_boundary(length) {
+                length -= 1;
+            }
+            writer.write_all(&[length as u8])?;
+            writer.write_all(&street.as_bytes()[..length])?;
+        }
+        Ok(())
+    })
+}
+
+// load back instructions saved by save_instructions (block type and size already read).
+pub fn load_instructions<R: Read>(reader: &mut R) -> Result<Vec<Instruction>, DecodeError> {
+    let instructions_number = reader.read_u32::<LittleEndian>()?;
+    (0..instructions_number)
+        .map(|_| {
+            let point = reader.read_u32::<LittleEndian>()? as usize;
+            let direction = TurnDirection::try_from(reader.read_u8()?)?;
+            let angle = reader.read_i16::<LittleEndian>()?;
+            let branch = reader.read_u8()?;
+            let branches = reader.read_u8()?;
+            let distance_to_next = reader.read_u32::<LittleEndian>()?;
+            let mut street = vec![0; reader.read_u8()? as usize];
+            reader.read_exact(&mut street)?;
+            let street = String::from_utf8(street)
+                .map_err(|e| DecodeError::InvalidData(format!("invalid street name: {e}")))?;
+            Ok(Instruction {
+                point,
+                direction,
+                angle,
+                branch,
+                branches,
+                distance_to_next,
+                street: (!street.is_empty()).then_some(street),
+            })
+        })
+        .collect()
+}
@@ -1,96 +1,253 @@
-use std::{collections::HashMap, io::Write};
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
 
+use byteorder::{LittleEndian, ReadBytesExt};
 use itertools::Itertools;
 
-use crate::{map::BlockType, Node};
+use crate::{
+    decoder::DecodeError,
+    map::{tile_index_size, write_block, BlockType, TilesOffsets, TILES_PER_GROUP},
+    utils::{compress, decompress, read_bytes},
+    Node,
+};
+
+// osm tags of an interest (name, opening hours...) shown by the watch on tap
+pub type InterestDetails = Vec<(String, String)>;
+
+// name and opening hours are always kept, other tags must be asked for
+pub const DEFAULT_INTERESTS_TAGS: [&str; 2] = ["name", "opening_hours"];
+
+// interests types are 0 for path waypoints, then the index (starting at 1)
+// of the requested key value, and this one for gpx waypoints away from the path.
+pub const GPX_WAYPOINT_INTEREST: usize = u8::MAX as usize;
+
+// interests are stored in a grid of tiles.
+// we keep the indices of the interests of each non-empty tile, tiles in increasing order.
+struct InterestsGrid {
+    first_tile: (isize, isize),
+    grid_size: (usize, usize),
+    tiles: Vec<(usize, Vec<usize>)>,
+}
+
+fn interests_grid<'a, I: Iterator<Item = &'a Node>>(nodes: I, side: f64) -> Option<InterestsGrid> {
+    // first tile is enough for interests
+    let interests_tiles = nodes
+        .map(|interest_node| interest_node.tiles(side).next().unwrap())
+        .collect::<Vec<_>>();
+    let (first_tile_x, last_tile_x) = interests_tiles
+        .iter()
+        .map(|(tx, _)| *tx)
+        .minmax()
+        .into_option()?;
+    let (first_tile_y, last_tile_y) = interests_tiles
+        .iter()
+        .map(|(_, ty)| *ty)
+        .minmax()
+        .into_option()?;
+
+    let grid_width = (last_tile_x + 1 - first_tile_x) as usize;
+    let grid_height = (last_tile_y + 1 - first_tile_y) as usize;
+
+    let mut tiled_interests: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (interest, (tx, ty)) in interests_tiles.into_iter().enumerate() {
+        let tile = (tx - first_tile_x) as usize + (ty - first_tile_y) as usize * grid_width;
+        tiled_interests.entry(tile).or_default().push(interest);
+    }
+    let mut tiles = tiled_interests.into_iter().collect::<Vec<_>>();
+    tiles.sort_unstable();
+    Some(InterestsGrid {
+        first_tile: (first_tile_x, first_tile_y),
+        grid_size: (grid_width, grid_height),
+        tiles,
+    })
+}
 
 pub fn save_tiled_interests<W: Write>(
     interests: &[(usize, Node)],
     side: f64,
     writer: &mut W,
 ) -> std::io::Result<()> {
-    if interests.is_empty() {
+    let Some(grid) = interests_grid(interests.iter().map(|(_, node)| node), side) else {
         return Ok(());
-    }
-    let mut tiled_interests: HashMap<usize, Vec<(usize, Node)>> = HashMap::new();
-    let (xmin, xmax) = interests
+    };
+    let (first_tile_x, first_tile_y) = grid.first_tile;
+    let (grid_width, grid_height) = grid.grid_size;
+    let xmin = first_tile_x as f64 * side;
+    let ymin = first_tile_y as f64 * side;
+
+    write_block(writer, BlockType::Interests, |writer| {
+        writer.write_all(&(first_tile_x as u32).to_le_bytes())?;
+        writer.write_all(&(first_tile_y as u32).to_le_bytes())?;
+        writer.write_all(&(grid_width as u32).to_le_bytes())?;
+        writer.write_all(&(grid_height as u32).to_le_bytes())?;
+        writer.write_all(&xmin.to_le_bytes())?;
+        writer.write_all(&ymin.to_le_bytes())?;
+        writer.write_all(&side.to_le_bytes())?;
+
+        //TODO: factorize with save_sizes_prefix
+        writer.write_all(&[16])?;
+        writer.write_all(&[3])?; // size taken by each interest
+        let bytes_per_tile_index = tile_index_size((grid_width, grid_height));
+        writer.write_all(&grid.tiles.len().to_le_bytes()[0..bytes_per_tile_index])?;
+        for (tile, _) in &grid.tiles {
+            writer.write_all(&tile.to_le_bytes()[0..bytes_per_tile_index])?;
+        }
+
+        for end in grid
+            .tiles
+            .iter()
+            .scan(0u16, |previous_end, (_, tile_interests)| {
+                *previous_end += tile_interests.len() as u16;
+                Some(*previous_end)
+            })
+        {
+            writer.write_all(&end.to_le_bytes())?;
+        }
+        for (tile, tile_interests) in &grid.tiles {
+            for (interest_type, interest_node) in tile_interests.iter().map(|i| &interests[*i]) {
+                writer.write_all(&[*interest_type as u8])?;
+                let tile_x = first_tile_x + (*tile % grid_width) as isize;
+                let tile_y = first_tile_y + (*tile / grid_width) as isize;
+                let encoded = interest_node.encode(tile_x, tile_y, side);
+                writer.write_all(&encoded)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+// load back interests saved by save_tiled_interests (block type and size already read).
+pub fn load_tiled_interests<R: Read>(reader: &mut R) -> Result<Vec<(usize, Node)>, DecodeError> {
+    let first_tile_x = reader.read_i32::<LittleEndian>()? as isize;
+    let first_tile_y = reader.read_i32::<LittleEndian>()? as isize;
+    let grid_width = reader.read_u32::<LittleEndian>()? as usize;
+    let grid_height = reader.read_u32::<LittleEndian>()? as usize;
+    let _xmin = reader.read_f64::<LittleEndian>()?;
+    let _ymin = reader.read_f64::<LittleEndian>()?;
+    let side = reader.read_f64::<LittleEndian>()?;
+
+    let tiles_offsets = TilesOffsets::load(reader, (grid_width, grid_height))?;
+    tiles_offsets.validate((grid_width, grid_height), 3)?;
+    let binary_interests = read_bytes(reader, tiles_offsets.end_offset())?;
+
+    let mut interests = Vec::new();
+    let mut start = 0;
+    for (tile, end) in tiles_offsets
+        .non_empty_tiles
         .iter()
-        .map(|(_, node)| node.x)
-        .minmax()
-        .into_option()
-        .unwrap();
+        .zip(&tiles_offsets.non_empty_tiles_ends)
+    {
+        let end = end * tiles_offsets.entry_size;
+        let tile_x = first_tile_x + (tile % grid_width) as isize;
+        let tile_y = first_tile_y + (tile / grid_width) as isize;
+        for (interest_type, cx, cy) in binary_interests[start..end].iter().tuples() {
+            let x = (tile_x as f64 + *cx as f64 / 255.) * side;
+            let y = (tile_y as f64 + *cy as f64 / 255.) * side;
+            interests.push((*interest_type as usize, Node::new(x, y)));
+        }
+        start = end;
+    }
+    Ok(interests)
+}
 
-    let (ymin, ymax) = interests
+// details of all interests, in the order of the interests block (one line each).
+// non-empty tiles are grouped by TILES_PER_GROUP and each group is compressed
+// so that the watch only decompresses the group of the interest it displays.
+// only tags with given keys are kept, in keys order.
+pub fn save_interests_details<W: Write>(
+    interests: &[(usize, Node, InterestDetails)],
+    keys: &[String],
+    side: f64,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    let lines = interests
         .iter()
-        .map(|(_, node)| node.y)
-        .minmax()
-        .into_option()
-        .unwrap();
-
-    let first_tile_x = (xmin / side).floor() as isize;
-    let first_tile_y = (ymin / side).floor() as isize;
-    let grid_width = 1isize.max((xmax / side).floor() as isize - first_tile_x) as usize;
-    let grid_height = 1isize.max((ymax / side).floor() as isize - first_tile_y) as usize;
-    let xmin = (xmin / side).floor() * side;
-    let ymin = (ymin / side).floor() * side;
-
-    for (tile, interest) in interests.iter().map(|(interest_type, interest_node)| {
-        (
-            interest_node
-                .tiles(side)
-                .map(|(tx, ty)| ((tx - first_tile_x) as usize, (ty - first_tile_y) as usize))
-                .map(|(tx, ty)| tx + ty * grid_width)
-                .next() // first tile is enough for interests
-                .unwrap(),
-            (*interest_type, *interest_node),
-        )
-    }) {
-        tiled_interests.entry(tile).or_default().push(interest);
+        .map(|(_, _, details)| {
+            keys.iter()
+                .filter_map(|key| {
+                    details
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, value)| format!("{key}={}", detail_label(value)))
+                })
+                .join("\t")
+        })
+        .collect::<Vec<_>>();
+    if lines.iter().all(|line| line.is_empty()) {
+        return Ok(());
     }
-    let mut non_empty_tiles = tiled_interests.keys().copied().collect::<Vec<usize>>();
-    non_empty_tiles.sort_unstable();
-
-    writer.write_all(&[BlockType::Interests as u8])?;
-
-    writer.write_all(&(first_tile_x as u32).to_le_bytes())?;
-    writer.write_all(&(first_tile_y as u32).to_le_bytes())?;
-    writer.write_all(&(grid_width as u32).to_le_bytes())?;
-    writer.write_all(&(grid_height as u32).to_le_bytes())?;
-    writer.write_all(&xmin.to_le_bytes())?;
-    writer.write_all(&ymin.to_le_bytes())?;
-    writer.write_all(&side.to_le_bytes())?;
-
-    //TODO: factorize with save_sizes_prefix
-    writer.write_all(&[16])?;
-    writer.write_all(&[3])?; // size taken by each interest
-    writer.write_all(&(non_empty_tiles.len() as u16).to_le_bytes())?;
-
-    let bytes_per_tile_index = if grid_width * grid_height > std::u16::MAX as usize {
-        3
-    } else {
-        2
+    let Some(grid) = interests_grid(interests.iter().map(|(_, node, _)| node), side) else {
+        return Ok(());
     };
-    for tile in &non_empty_tiles {
-        writer.write_all(&tile.to_le_bytes()[0..bytes_per_tile_index])?;
-    }
+    let compressed_groups = grid
+        .tiles
+        .chunks(TILES_PER_GROUP)
+        .map(|group| {
+            let mut text = String::new();
+            for interest in group.iter().flat_map(|(_, tile_interests)| tile_interests) {
+                text.push_str(&lines[*interest]);
+                text.push('\n');
+            }
+            compress(text.as_bytes())
+        })
+        .collect::<Vec<_>>();
 
-    for end in non_empty_tiles.iter().scan(0u16, |previous_end, tile_id| {
-        let tile_size = tiled_interests[tile_id].len() as u16;
-        *previous_end += tile_size;
-        Some(*previous_end)
-    }) {
-        writer.write_all(&end.to_le_bytes())?;
+    write_block(writer, BlockType::InterestsDetails, |writer| {
+        writer.write_all(&[TILES_PER_GROUP as u8])?;
+        writer.write_all(&(compressed_groups.len() as u16).to_le_bytes())?;
+        for end in compressed_groups.iter().scan(0, |end, group| {
+            *end += group.len();
+            Some(*end)
+        }) {
+            writer.write_all(&(end as u32).to_le_bytes())?;
+        }
+        compressed_groups
+            .iter()
+            .try_for_each(|group| writer.write_all(group))
+    })
+}
+
+// values are displayed like streets names, tabs separate tags
+fn detail_label(value: &str) -> String {
+    crate::streets::street_label(value).replace('\t', " ")
+}
+
+// load back details saved by save_interests_details (block type and size already read).
+pub fn load_interests_details<R: Read>(
+    reader: &mut R,
+) -> Result<Vec<InterestDetails>, DecodeError> {
+    let tiles_per_group = reader.read_u8()?;
+    if tiles_per_group == 0 {
+        return Err(DecodeError::BadSize("empty tiles groups".to_owned()));
     }
-    for tile in &non_empty_tiles {
-        for (interest_type, interest_node) in &tiled_interests[tile] {
-            writer.write_all(&[*interest_type as u8])?;
-            let tile_x = first_tile_x + (*tile as usize % grid_width) as isize;
-            let tile_y = first_tile_y + (*tile as usize / grid_width) as isize;
-            let encoded = interest_node.encode(tile_x, tile_y, side);
-            // eprintln!("{interest_node:?} encodes as {encoded:?}, tile is {tile_x}/{tile_y}");
-            writer.write_all(&encoded)?;
+    let groups_number = reader.read_u16::<LittleEndian>()? as usize;
+    let groups_ends = (0..groups_number)
+        .map(|_| reader.read_u32::<LittleEndian>().map(|end| end as usize))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let compressed_groups = read_bytes(reader, groups_ends.last().copied().unwrap_or_default())?;
+    let mut details = Vec::new();
+    for (start, end) in std::iter::once(0).chain(groups_ends).tuple_windows() {
+        let group = compressed_groups
+            .get(start..end)
+            .ok_or_else(|| DecodeError::BadSize("invalid details groups offsets".to_owned()))?;
+        let text = String::from_utf8(decompress(group))
+            .map_err(|e| DecodeError::InvalidData(format!("invalid interests details: {e}")))?;
+        for line in text.lines() {
+            details.push(
+                line.split('\t')
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| {
+                        tag.split_once('=')
+                            .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                            .ok_or_else(|| {
+                                DecodeError::InvalidData(format!("invalid interest tag {tag}"))
+                            })
+                    })
+                    .collect::<Result<_, _>>()?,
+            );
         }
     }
-
-    Ok(())
+    Ok(details)
 }
@@ -1,32 +1,71 @@
 use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
+#[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
 mod gps;
 pub use gps::{
-    disable_elevation, get_gps_content, get_gps_map_svg, get_polygon, get_polyline, gps_from_area,
-    has_heights, load_gps_from_file, load_gps_from_string, request_map, Gps,
+    add_interests_tag, disable_elevation, enable_edge_attributes, enable_precise_elevation,
+    enable_tiles_compression, get_gps_content, get_gps_map_svg, get_polygon, get_polyline,
+    gps_from_area, has_heights, load_gps_from_file, load_gps_from_string, request_map,
+    set_overpass_endpoint, set_overpass_timeout, set_overpass_user_agent, set_profile, Gps,
 };
 mod node;
 pub use node::Node;
+mod attributes;
+pub use attributes::{EdgeAttributes, Oneway, RoadClass};
 // mod geometry;
 // pub use geometry::inflate_polyline;
 mod osm;
-pub use osm::{parse_osm_xml, request};
+mod profile;
+pub use osm::{
+    overpass_query, parse_osm_xml, request, OsmData, OsmFile, OsmSource, Overpass, Route,
+    DEFAULT_OVERPASS_ENDPOINT, DEFAULT_USER_AGENT,
+};
+pub use profile::{Exclusion, Profile, TagRule};
 mod simplify;
-pub use simplify::{optimal_simplification, optimal_simplification2, simplify_path};
+pub use simplify::{
+    optimal_simplification, optimal_simplification2, simplify_path, simplify_path_indices,
+};
 mod utils;
 pub use utils::grid_coordinates_between;
 pub mod map;
-pub use map::{load_maps_and_interests, maps_and_interests_from_string, Map};
+#[cfg(feature = "pbf")]
+mod pbf;
+#[cfg(feature = "pbf")]
+pub use map::load_maps_and_interests_from_pbf;
+pub use map::{
+    load_maps_and_interests, maps_and_interests_from_osm, maps_and_interests_from_reader,
+    maps_and_interests_from_string, BlockType, Map, GPS_FORMAT_VERSION, GPS_MAGIC,
+};
+#[cfg(feature = "pbf")]
+pub use pbf::{parse_osm_objects, parse_osm_pbf};
 mod graph;
 mod svg;
 pub use svg::{save_svg, Svg, SvgW};
 mod gpx;
-pub use crate::gpx::{detect_sharp_turns, parse_gpx_points, request_maps_from};
+pub use crate::gpx::{
+    detect_sharp_turns, load_heights, load_path, load_times, parse_gpx_points, path_heights,
+    path_times, request_maps_from, save_heights, save_path, save_times, segments_ranges,
+    GpxContent, PATH_RESOLUTION,
+};
 mod interests;
 mod streets;
-pub use interests::save_tiled_interests;
+pub use interests::{
+    load_interests_details, load_tiled_interests, save_interests_details, save_tiled_interests,
+    InterestDetails, DEFAULT_INTERESTS_TAGS, GPX_WAYPOINT_INTEREST,
+};
+pub use streets::decode_streets;
+mod instructions;
+pub use instructions::{
+    load_instructions, path_instructions, save_instructions, Instruction, TurnDirection,
+};
+mod metadata;
+pub use metadata::{load_metadata, save_metadata, OSM_ATTRIBUTION};
+mod decoder;
+pub use decoder::{load_gps_file, DecodeError, GpsFile};
+mod check;
+pub use check::{check_gps, Violation};
 
 pub type TileKey = (isize, isize);
 pub type WayId = u64;
@@ -34,13 +73,26 @@ pub type NodeId = u64;
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug, Hash)]
 pub struct CWayId {
-    pub(crate) tile_number: u16,
+    pub(crate) tile_number: u32,
+    pub(crate) sub_tile: u8,
     pub(crate) local_way_id: u8,
 }
 
+impl CWayId {
+    // id of the start (0) or end (1) node of the way
+    pub(crate) fn node(&self, end: u16) -> CNodeId {
+        CNodeId {
+            tile_number: self.tile_number,
+            sub_tile: self.sub_tile,
+            local_node_id: 2 * self.local_way_id as u16 + end,
+        }
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub struct CNodeId {
-    tile_number: u16,
+    tile_number: u32,
+    sub_tile: u8,
     local_node_id: u16,
 }
 
@@ -136,6 +188,7 @@ pub fn simplify_ways(
     nodes: &mut Vec<Node>,
     ways: &mut Vec<Vec<NodeId>>,
     streets: &mut HashMap<String, Vec<WayId>>,
+    attributes: &mut HashMap<WayId, EdgeAttributes>,
 ) {
     let mut new_nodes = HashMap::new();
     let mut new_ways: Vec<Vec<NodeId>> = Vec::new();
@@ -174,6 +227,14 @@ pub fn simplify_ways(
         *street = new_street;
     }
     streets.retain(|_, s| !s.is_empty());
+    *attributes = std::mem::take(attributes)
+        .into_iter()
+        .filter_map(|(old_id, way_attributes)| {
+            ids_changes
+                .get(&old_id)
+                .map(|new_id| (*new_id, way_attributes))
+        })
+        .collect();
 }
 
 fn compute_node_degrees(ways: &HashMap<WayId, Vec<NodeId>>) -> HashMap<NodeId, usize> {
@@ -190,6 +251,7 @@ fn compute_node_degrees(ways: &HashMap<WayId, Vec<NodeId>>) -> HashMap<NodeId, u
 pub fn sanitize_ways(
     ways: HashMap<WayId, Vec<NodeId>>,
     streets: &mut HashMap<String, Vec<WayId>>,
+    attributes: &mut HashMap<WayId, EdgeAttributes>,
 ) -> Vec<Vec<NodeId>> {
     let degrees = compute_node_degrees(&ways);
     let mut new_ways = Vec::new();
@@ -233,13 +295,32 @@ pub fn sanitize_ways(
             .collect::<Vec<_>>();
         *street_ways = new_street_ways;
     }
+    renumber_attributes(attributes, &ids_changes);
     new_ways
 }
 
+// attributes follow the ways they were cut into
+fn renumber_attributes(
+    attributes: &mut HashMap<WayId, EdgeAttributes>,
+    ids_changes: &HashMap<WayId, Vec<WayId>>,
+) {
+    *attributes = std::mem::take(attributes)
+        .into_iter()
+        .flat_map(|(old_id, way_attributes)| {
+            ids_changes
+                .get(&old_id)
+                .into_iter()
+                .flatten()
+                .map(move |new_id| (*new_id, way_attributes))
+        })
+        .collect();
+}
+
 // cut ways such that we only get segments.
 pub fn cut_ways_into_edges(
     ways: Vec<Vec<NodeId>>,
     streets: &mut HashMap<String, Vec<WayId>>,
+    attributes: &mut HashMap<WayId, EdgeAttributes>,
 ) -> Vec<[NodeId; 2]> {
     let mut new_ways = Vec::new();
     let mut ids_changes: HashMap<WayId, Vec<WayId>> = HashMap::new();
@@ -260,6 +341,7 @@ pub fn cut_ways_into_edges(
             .collect::<Vec<_>>();
         *street_ways = new_street_ways;
     }
+    renumber_attributes(attributes, &ids_changes);
 
     new_ways
 }
@@ -1,4 +1,4 @@
-use gps::{disable_elevation, Node};
+use gps::Node;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -35,17 +35,37 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 Node::new(xmin + width, ymin + height),
                 Node::new(xmin, ymin + height),
             ],
-            false,
+            gps::Profile::default(),
         );
         (gps, map_name)
     };
     let mut gps_name: std::path::PathBuf = map_name.clone();
     gps_name.set_extension("gps");
 
-    if gps.load_map(&map_name, &key_values).is_err() {
+    if let Ok(endpoint) = std::env::var("OVERPASS_ENDPOINT") {
+        gps::set_overpass_endpoint(&mut gps, &endpoint);
+    }
+    // OSM_PBF=region.osm.pbf builds the maps without network
+    // INTERESTS_TAGS=fee,seasonal also saves these tags of interests
+    if let Ok(tags) = std::env::var("INTERESTS_TAGS") {
+        for tag in tags.split(',') {
+            gps::add_interests_tag(&mut gps, tag);
+        }
+    }
+    #[cfg(feature = "pbf")]
+    let from_pbf = match std::env::var("OSM_PBF") {
+        Ok(pbf_path) => {
+            gps.load_pbf(pbf_path, &key_values)?;
+            true
+        }
+        Err(_) => false,
+    };
+    #[cfg(not(feature = "pbf"))]
+    let from_pbf = false;
+    if !from_pbf && gps.load_map(&map_name, &key_values).is_err() {
         gps.request_maps(&key_values, Some(map_name)).await
     }
-    // disable_elevation(&mut gps);
+    // gps::disable_elevation(&mut gps);
     gps.save_svg("map.svg").expect("failed saving svg file");
 
     let mut writer = std::io::BufWriter::new(std::fs::File::create(gps_name)?);
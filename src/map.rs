@@ -1,4 +1,4 @@
-use futures::io::AsyncWriteExt;
+use byteorder::{LittleEndian, ReadBytesExt};
 use itertools::Itertools;
 use std::{
     collections::{HashMap, HashSet},
@@ -14,54 +14,478 @@ pub const DEFAULT_SIDE: f64 = 1. / 750.; // excellent value
                                          // Note that the best value for size is 1/500
                                          // But we go for 1/750 because this enables is to use less pixels in the watch's display
 
-use crate::{CNodeId, CWayId, Node, NodeId, TileKey, WayId};
+use crate::{
+    decoder::DecodeError,
+    interests::InterestDetails,
+    osm::OsmData,
+    profile::Profile,
+    utils::{compress, crc32, decompress, read_bytes},
+    CNodeId, CWayId, EdgeAttributes, Node, NodeId, TileKey, WayId,
+};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BlockType {
     Tiles,
     Streets,
     Path,
     Interests,
     Heights,
+    CompressedTiles,
+    Metadata,
+    Times,
+    Instructions,
+    InterestsDetails,
+}
+
+impl TryFrom<u8> for BlockType {
+    type Error = DecodeError;
+
+    fn try_from(tag: u8) -> Result<Self, DecodeError> {
+        match tag {
+            0 => Ok(BlockType::Tiles),
+            1 => Ok(BlockType::Streets),
+            2 => Ok(BlockType::Path),
+            3 => Ok(BlockType::Interests),
+            4 => Ok(BlockType::Heights),
+            5 => Ok(BlockType::CompressedTiles),
+            6 => Ok(BlockType::Metadata),
+            7 => Ok(BlockType::Times),
+            8 => Ok(BlockType::Instructions),
+            9 => Ok(BlockType::InterestsDetails),
+            _ => Err(DecodeError::UnknownBlockType(tag)),
+        }
+    }
+}
+
+// every .gps file starts with these magic bytes, followed by the format version
+// and the number of blocks.
+pub const GPS_MAGIC: [u8; 4] = *b"GIPY";
+pub const GPS_FORMAT_VERSION: u16 = 8;
+
+// set on the block type when the block content is followed by its crc32.
+const CHECKSUM_FLAG: u8 = 0x80;
+
+pub fn save_gps_header<W: Write>(blocks_number: usize, writer: &mut W) -> std::io::Result<()> {
+    let blocks_number = u16::try_from(blocks_number).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("too many blocks ({blocks_number}) for the file header"),
+        )
+    })?;
+    writer.write_all(&GPS_MAGIC)?;
+    writer.write_all(&GPS_FORMAT_VERSION.to_le_bytes())?;
+    writer.write_all(&blocks_number.to_le_bytes())
+}
+
+// load the file header and return the number of blocks.
+pub fn load_gps_header<R: Read>(reader: &mut R) -> Result<usize, DecodeError> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != GPS_MAGIC {
+        return Err(DecodeError::NotGps);
+    }
+    let version = reader.read_u16::<LittleEndian>()?;
+    // blocks layouts change between versions so we only read the current one
+    if version != GPS_FORMAT_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    Ok(reader.read_u16::<LittleEndian>()? as usize)
+}
+
+// each block is its type, its size, its content and the crc32 of the content.
+// the size (crc included) enables readers to skip blocks they do not know.
+pub(crate) fn write_block<W: Write, F: FnOnce(&mut Vec<u8>) -> std::io::Result<()>>(
+    writer: &mut W,
+    block_type: BlockType,
+    content: F,
+) -> std::io::Result<()> {
+    let mut binary_content = Vec::new();
+    content(&mut binary_content)?;
+    writer.write_all(&[block_type as u8 | CHECKSUM_FLAG])?;
+    writer.write_all(&(binary_content.len() as u32 + 4).to_le_bytes())?;
+    writer.write_all(&binary_content)?;
+    writer.write_all(&crc32(&binary_content).to_le_bytes())
+}
+
+// read next block, check its crc if any and return its type and content.
+// block number is only used in error messages.
+pub fn read_block<R: Read>(
+    reader: &mut R,
+    block_number: usize,
+) -> Result<(u8, Vec<u8>), DecodeError> {
+    let tag = reader.read_u8()?;
+    let block_type = tag & !CHECKSUM_FLAG;
+    let block_error = |error| DecodeError::InBlock {
+        block: block_number,
+        block_type,
+        error: Box::new(error),
+    };
+    let size = reader.read_u32::<LittleEndian>()? as usize;
+    let mut content = Vec::new();
+    reader
+        .take(size as u64)
+        .read_to_end(&mut content)
+        .map_err(|e| block_error(e.into()))?;
+    if content.len() != size {
+        return Err(block_error(DecodeError::Truncated));
+    }
+    if tag & CHECKSUM_FLAG != 0 {
+        if content.len() < 4 {
+            return Err(block_error(DecodeError::BadSize(format!(
+                "size {size} is too small for the checksum"
+            ))));
+        }
+        let stored_crc = content.split_off(content.len() - 4);
+        if crc32(&content).to_le_bytes() != stored_crc.as_slice() {
+            return Err(block_error(DecodeError::BadChecksum));
+        }
+    }
+    Ok((block_type, content))
+}
+
+// offsets of the non-empty tiles, as written by save_sizes_prefix
+// (and save_tiled_interests).
+#[derive(Debug)]
+pub struct TilesOffsets {
+    pub entry_size: usize,
+    pub non_empty_tiles: Vec<usize>,
+    pub non_empty_tiles_ends: Vec<usize>,
+}
+
+impl TilesOffsets {
+    // offsets are loaded as they are, see validate for the checks
+    pub fn load<R: Read>(reader: &mut R, grid_size: (usize, usize)) -> Result<Self, DecodeError> {
+        let type_size = reader.read_u8()?;
+        let bytes_number = match type_size {
+            16 => 2,
+            24 => 3,
+            _ => {
+                return Err(DecodeError::BadSize(format!(
+                    "invalid tiles offsets size {type_size}"
+                )))
+            }
+        };
+        let entry_size = reader.read_u8()? as usize;
+        let bytes_per_tile_index = tile_index_size(grid_size);
+        let non_empty_tiles_number =
+            reader.read_uint::<LittleEndian>(bytes_per_tile_index)? as usize;
+        let non_empty_tiles = (0..non_empty_tiles_number)
+            .map(|_| reader.read_uint::<LittleEndian>(bytes_per_tile_index))
+            .map(|tile| tile.map(|t| t as usize))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let non_empty_tiles_ends = (0..non_empty_tiles_number)
+            .map(|_| reader.read_uint::<LittleEndian>(bytes_number))
+            .map(|end| end.map(|e| e as usize))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        Ok(TilesOffsets {
+            entry_size,
+            non_empty_tiles,
+            non_empty_tiles_ends,
+        })
+    }
+
+    // check tiles are increasing, inside the grid and their ends are increasing
+    // (we can then safely index the grid and the content).
+    pub fn validate(
+        &self,
+        grid_size: (usize, usize),
+        entry_size: usize,
+    ) -> Result<(), DecodeError> {
+        if self.entry_size != entry_size {
+            return Err(DecodeError::BadSize(format!(
+                "entries take {} bytes instead of {entry_size}",
+                self.entry_size
+            )));
+        }
+        let tiles_number = grid_tiles_number(grid_size)?;
+        if let Some(tile) = self.non_empty_tiles.iter().find(|t| **t >= tiles_number) {
+            return Err(DecodeError::TileOutOfRange {
+                tile: *tile,
+                tiles_number,
+            });
+        }
+        if !self.non_empty_tiles.is_sorted_by(|t1, t2| t1 < t2) {
+            return Err(DecodeError::InvalidData(
+                "tiles are not in increasing order".to_owned(),
+            ));
+        }
+        if !std::iter::once(&0)
+            .chain(&self.non_empty_tiles_ends)
+            .is_sorted_by(|e1, e2| e1 < e2)
+        {
+            return Err(DecodeError::BadSize(
+                "non-empty tiles ends are not increasing".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    // number of entries in all tiles
+    pub fn entries_number(&self) -> usize {
+        self.non_empty_tiles_ends
+            .last()
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // size in bytes of all tiles content
+    pub fn end_offset(&self) -> usize {
+        self.entries_number() * self.entry_size
+    }
+
+    // rebuild the end offset (in bytes) of each tile of the grid,
+    // empty tiles included.
+    pub fn tiles_sizes_prefix(&self, tiles_number: usize) -> Vec<usize> {
+        let mut prefix = Vec::with_capacity(tiles_number);
+        let mut current_end = 0;
+        let mut non_empty_tiles = self
+            .non_empty_tiles
+            .iter()
+            .zip(&self.non_empty_tiles_ends)
+            .peekable();
+        for tile in 0..tiles_number {
+            if let Some((_, end)) = non_empty_tiles.next_if(|(t, _)| **t == tile) {
+                current_end = end * self.entry_size;
+            }
+            prefix.push(current_end);
+        }
+        prefix
+    }
+}
+
+// we store a prefix for each tile of the grid so we refuse absurd grids
+pub const MAX_GRID_TILES: usize = 1 << 26;
+
+// number of tiles in the grid, if we can handle it
+pub(crate) fn grid_tiles_number(grid_size: (usize, usize)) -> Result<usize, DecodeError> {
+    grid_size
+        .0
+        .checked_mul(grid_size.1)
+        .filter(|tiles_number| *tiles_number <= MAX_GRID_TILES)
+        .ok_or_else(|| {
+            DecodeError::BadSize(format!("grid {}x{} is too large", grid_size.0, grid_size.1))
+        })
+}
+
+// tiles indices take 2 bytes when they fit, 3 or 4 on larger grids
+pub(crate) fn tile_index_size(grid_size: (usize, usize)) -> usize {
+    let tiles_number = grid_size.0.saturating_mul(grid_size.1);
+    if tiles_number <= u16::MAX as usize {
+        2
+    } else if tiles_number <= 1 << 24 {
+        3
+    } else {
+        4
+    }
+}
+
+// load back sub-tiles saved by save_sub_tiles
+pub(crate) fn load_sub_tiles<R: Read>(
+    reader: &mut R,
+    grid_size: (usize, usize),
+) -> Result<HashMap<usize, Vec<usize>>, DecodeError> {
+    let bytes_per_tile_index = tile_index_size(grid_size);
+    let subdivided_tiles_number = reader.read_uint::<LittleEndian>(bytes_per_tile_index)?;
+    (0..subdivided_tiles_number)
+        .map(|_| {
+            let tile = reader.read_uint::<LittleEndian>(bytes_per_tile_index)? as usize;
+            let sub_tiles_number = reader.read_u8()?;
+            let mut end = 0;
+            let ends = (0..sub_tiles_number)
+                .map(|_| {
+                    end += reader.read_u8()? as usize * 4;
+                    Ok(end)
+                })
+                .collect::<std::io::Result<Vec<_>>>()?;
+            Ok((tile, ends))
+        })
+        .collect()
 }
 
+// load back the attributes saved by save_tiles, if any
+pub(crate) fn load_attributes<R: Read>(
+    reader: &mut R,
+    ways_number: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    match reader.read_u8()? {
+        0 => Ok(Vec::new()),
+        1 => {
+            let attributes = read_bytes(reader, ways_number)?;
+            attributes
+                .iter()
+                .try_for_each(|byte| EdgeAttributes::try_from(*byte).map(|_| ()))?;
+            Ok(attributes)
+        }
+        flag => Err(DecodeError::InvalidData(format!(
+            "invalid attributes flag {flag}"
+        ))),
+    }
+}
+
+// load back and decompress all groups saved by save_compressed_groups
+pub(crate) fn load_compressed_groups<R: Read>(
+    reader: &mut R,
+    tiles_offsets: &TilesOffsets,
+) -> Result<Vec<u8>, DecodeError> {
+    let tiles_per_group = reader.read_u8()? as usize;
+    if tiles_per_group == 0 {
+        return Err(DecodeError::BadSize("empty tiles groups".to_owned()));
+    }
+    let groups_number = tiles_offsets
+        .non_empty_tiles
+        .len()
+        .div_ceil(tiles_per_group);
+    let groups_ends = (0..groups_number)
+        .map(|_| reader.read_u32::<LittleEndian>().map(|end| end as usize))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let compressed_groups = read_bytes(reader, groups_ends.last().copied().unwrap_or_default())?;
+    // byte span of each group in the decompressed tiles
+    let groups_spans = std::iter::once(0)
+        .chain(
+            tiles_offsets
+                .non_empty_tiles_ends
+                .chunks(tiles_per_group)
+                .map(|ends| ends.last().copied().unwrap_or_default() * tiles_offsets.entry_size),
+        )
+        .tuple_windows();
+    let mut binary_ways = Vec::new();
+    for (group, ((start, end), (span_start, span_end))) in std::iter::once(0)
+        .chain(groups_ends)
+        .tuple_windows()
+        .zip(groups_spans)
+        .enumerate()
+    {
+        if start > end || end > compressed_groups.len() {
+            return Err(DecodeError::BadSize(
+                "invalid tiles groups offsets".to_owned(),
+            ));
+        }
+        let group_ways = decompress(&compressed_groups[start..end]);
+        if span_end.checked_sub(span_start) != Some(group_ways.len()) {
+            return Err(DecodeError::BadSize(format!(
+                "decompressed tiles group {group} does not match its offsets"
+            )));
+        }
+        binary_ways.extend(group_ways);
+    }
+    Ok(binary_ways)
+}
+
+// non-empty tiles are compressed by groups of TILES_PER_GROUP
+// so the watch only decompresses the tiles around it.
+pub const TILES_PER_GROUP: usize = 8;
+
 pub struct Map {
     pub color: [u8; 3],
     pub binary_ways: Vec<u8>,
     pub start_coordinates: (f64, f64),
     pub first_tile: (isize, isize),
     pub tiles_sizes_prefix: Vec<usize>,
+    // end offsets (in bytes, from the tile start) of the sub-tiles
+    // of each tile with more ways than MAX_TILE_WAYS
+    pub sub_tiles_ends: HashMap<usize, Vec<usize>>,
     pub grid_size: (usize, usize),
     pub side: f64,
     pub streets: HashMap<String, Vec<CWayId>>,
+    // one attributes byte per way (in binary_ways order), empty if we have none
+    pub attributes: Vec<u8>,
 }
 
+#[allow(clippy::type_complexity)]
 pub fn load_maps_and_interests<P: AsRef<Path>>(
     path: P,
     key_values: &[(String, String)],
-    ski: bool,
-) -> std::io::Result<(Vec<Map>, Vec<(usize, Node)>)> {
-    let mut answer = Vec::new();
-    std::io::BufReader::new(std::fs::File::open(path.as_ref())?).read_to_end(&mut answer)?;
-    let string = std::str::from_utf8(&answer).unwrap();
-    let side = if ski {
+    profile: Profile,
+    edge_attributes: bool,
+) -> std::io::Result<(Vec<Map>, Vec<(usize, Node, InterestDetails)>)> {
+    let open = || Ok(std::io::BufReader::new(std::fs::File::open(path.as_ref())?));
+    maps_and_interests_from_reader(
+        open,
+        key_values,
+        profile,
+        edge_attributes,
+        tiles_side(profile),
+    )
+}
+
+// ski maps are less dense, we can use larger tiles
+pub(crate) fn tiles_side(profile: Profile) -> f64 {
+    if profile == Profile::Ski {
         1. / 150.
     } else {
-        crate::map::DEFAULT_SIDE
-    };
-    Ok(maps_and_interests_from_string(
-        string, key_values, ski, side,
+        DEFAULT_SIDE
+    }
+}
+
+// build maps from a .osm.pbf extract, keeping what is inside the polygons
+#[cfg(feature = "pbf")]
+#[allow(clippy::type_complexity)]
+pub fn load_maps_and_interests_from_pbf<P: AsRef<Path>>(
+    path: P,
+    polygons: &[Vec<Node>],
+    key_values: &[(String, String)],
+    profile: Profile,
+    edge_attributes: bool,
+) -> std::io::Result<(Vec<Map>, Vec<(usize, Node, InterestDetails)>)> {
+    crate::log("map: reading pbf");
+    let osm = crate::parse_osm_pbf(path, polygons, key_values, profile)?;
+    Ok(maps_and_interests_from_osm(
+        osm,
+        profile,
+        edge_attributes,
+        tiles_side(profile),
     ))
 }
 
+#[allow(clippy::type_complexity)]
 pub fn maps_and_interests_from_string(
     s: &str,
     key_values: &[(String, String)],
-    ski: bool,
+    profile: Profile,
+    edge_attributes: bool,
+    side: f64,
+) -> std::io::Result<(Vec<Map>, Vec<(usize, Node, InterestDetails)>)> {
+    maps_and_interests_from_reader(
+        || Ok(s.as_bytes()),
+        key_values,
+        profile,
+        edge_attributes,
+        side,
+    )
+}
+
+#[allow(clippy::type_complexity)]
+// the reader is opened again if interests need ways we did not keep
+pub fn maps_and_interests_from_reader<R: Read, F: FnMut() -> std::io::Result<R>>(
+    open: F,
+    key_values: &[(String, String)],
+    profile: Profile,
+    edge_attributes: bool,
     side: f64,
-) -> (Vec<Map>, Vec<(usize, Node)>) {
+) -> std::io::Result<(Vec<Map>, Vec<(usize, Node, InterestDetails)>)> {
     crate::log("map: parsing xml");
-    let (nodes, mut ways, mut streets, pistes, interests) = crate::parse_osm_xml(s, key_values);
-    if ski {
+    let osm = crate::parse_osm_xml(open, key_values, profile)?;
+    Ok(maps_and_interests_from_osm(
+        osm,
+        profile,
+        edge_attributes,
+        side,
+    ))
+}
+
+// edge attributes are only used if asked for, they keep ways apart
+// when simplifying and deduplicating them.
+pub fn maps_and_interests_from_osm(
+    osm: OsmData,
+    profile: Profile,
+    edge_attributes: bool,
+    side: f64,
+) -> (Vec<Map>, Vec<(usize, Node, InterestDetails)>) {
+    let (nodes, ways, mut streets, pistes, interests, routes, mut attributes) = osm;
+    if !edge_attributes {
+        attributes.clear();
+    }
+    if profile == Profile::Ski {
         // red is 254 because at 255 gipy would display it thick
         let colors = [
             [0, 255, 0],
@@ -72,8 +496,7 @@ pub fn maps_and_interests_from_string(
         ];
         let mut maps = Vec::new();
         for (color, pistes) in colors.into_iter().zip(&pistes) {
-            let nodes = nodes.clone();
-            let mut ways = ways
+            let ways = ways
                 .iter()
                 .filter(|&(id, _)| pistes.contains(id))
                 .map(|(id, nodes)| (*id, nodes.clone()))
@@ -81,56 +504,116 @@ pub fn maps_and_interests_from_string(
             if ways.is_empty() {
                 continue;
             }
-            let mut streets = HashMap::new();
-            let mut renamed_nodes = crate::rename_nodes(nodes, &mut ways);
-            let mut ways = crate::sanitize_ways(ways, &mut streets);
-            crate::simplify_ways(&mut renamed_nodes, &mut ways, &mut streets);
-            crate::cut_segments_on_tiles(&mut renamed_nodes, &mut ways, side);
-            let ways = crate::cut_ways_into_edges(ways, &mut streets);
-            let tiles = crate::group_ways_in_tiles(&renamed_nodes, &ways, side);
-            let map = Map::new(color, &renamed_nodes, &ways, streets, &tiles, side);
-            maps.push(map);
+            maps.push(build_map(
+                color,
+                nodes.clone(),
+                ways,
+                HashMap::new(),
+                &attributes,
+                side,
+            ));
         }
         if maps.is_empty() {
             crate::log("map: no ski pistes found");
         }
         (maps, interests)
     } else {
+        // routes come as extra layers, after the main map
+        let mut layers = Vec::new();
+        for (color, kinds) in [
+            ([254, 0, 0], ["hiking", "foot"]),
+            ([0, 0, 254], ["bicycle", "mtb"]),
+        ] {
+            let routes = routes
+                .iter()
+                .filter(|route| kinds.contains(&route.kind.as_str()))
+                .collect::<Vec<_>>();
+            let route_ways = routes
+                .iter()
+                .flat_map(|route| &route.ways)
+                .map(|id| (*id, ways[id].clone()))
+                .collect::<HashMap<_, _>>();
+            if route_ways.is_empty() {
+                continue;
+            }
+            let mut route_streets: HashMap<String, Vec<WayId>> = HashMap::new();
+            for route in &routes {
+                if let Some(label) = route.label() {
+                    route_streets
+                        .entry(label.to_owned())
+                        .or_default()
+                        .extend(&route.ways);
+                }
+            }
+            layers.push(build_map(
+                color,
+                nodes.clone(),
+                route_ways,
+                route_streets,
+                &attributes,
+                side,
+            ));
+        }
+        // routes names also show up on the main map
+        for route in &routes {
+            if let Some(label) = route.label() {
+                streets
+                    .entry(label.to_owned())
+                    .or_default()
+                    .extend(&route.ways);
+            }
+        }
         crate::log("map: building");
-        let mut renamed_nodes = crate::rename_nodes(nodes, &mut ways);
-        let mut ways = crate::sanitize_ways(ways, &mut streets);
-        crate::simplify_ways(&mut renamed_nodes, &mut ways, &mut streets);
-        crate::cut_segments_on_tiles(&mut renamed_nodes, &mut ways, side);
-        let ways = crate::cut_ways_into_edges(ways, &mut streets);
-        let tiles = crate::group_ways_in_tiles(&renamed_nodes, &ways, side);
+        let map = build_map([0, 0, 0], nodes, ways, streets, &attributes, side);
         crate::log("map: done");
-        (
-            vec![Map::new(
-                [0, 0, 0],
-                &renamed_nodes,
-                &ways,
-                streets,
-                &tiles,
-                side,
-            )],
-            interests,
-        )
+        (std::iter::once(map).chain(layers).collect(), interests)
     }
 }
 
+fn build_map(
+    color: [u8; 3],
+    nodes: HashMap<NodeId, Node>,
+    mut ways: HashMap<WayId, Vec<NodeId>>,
+    mut streets: HashMap<String, Vec<WayId>>,
+    attributes: &HashMap<WayId, EdgeAttributes>,
+    side: f64,
+) -> Map {
+    let mut attributes = ways
+        .keys()
+        .filter_map(|id| attributes.get(id).map(|a| (*id, *a)))
+        .collect();
+    let mut renamed_nodes = crate::rename_nodes(nodes, &mut ways);
+    let mut ways = crate::sanitize_ways(ways, &mut streets, &mut attributes);
+    crate::simplify_ways(&mut renamed_nodes, &mut ways, &mut streets, &mut attributes);
+    crate::cut_segments_on_tiles(&mut renamed_nodes, &mut ways, side);
+    let ways = crate::cut_ways_into_edges(ways, &mut streets, &mut attributes);
+    let tiles = crate::group_ways_in_tiles(&renamed_nodes, &ways, side);
+    Map::new(
+        color,
+        &renamed_nodes,
+        &ways,
+        streets,
+        &attributes,
+        &tiles,
+        side,
+    )
+}
+
 impl Map {
     pub fn new(
         color: [u8; 3],
         nodes: &[Node],
         ways: &[[NodeId; 2]],
         streets: HashMap<String, Vec<WayId>>,
+        attributes: &HashMap<WayId, EdgeAttributes>,
         tiles: &HashMap<TileKey, Vec<WayId>>,
         side: f64,
     ) -> Self {
         let mut binary_ways = Vec::new();
+        let mut binary_attributes = Vec::new();
         let mut tiles_sizes_prefix = Vec::new();
+        let mut sub_tiles_ends = HashMap::new();
         let mut ids_changes = HashMap::new();
-        let mut local_ids_changes: HashMap<CWayId, CWayId> = HashMap::new();
         let (xmin, xmax) = tiles
             .keys()
             .map(|(x, _)| x)
@@ -149,42 +632,69 @@ impl Map {
         for y in ymin..=ymax {
             for x in xmin..=xmax {
                 if let Some(tile_ways) = tiles.get(&(x, y)) {
-                    let mut ways = compress_tile(
-                        nodes,
-                        ways,
-                        x,
-                        y,
-                        tile_ways,
-                        side,
-                        &mut ids_changes,
-                        tile_id,
-                    );
-                    deduplicate_ways(&mut ways, tile_id, &mut local_ids_changes);
-                    binary_ways.extend(ways.iter().flatten().flatten().copied());
+                    let mut tile_ids_changes = HashMap::new();
+                    let mut ways =
+                        compress_tile(nodes, ways, x, y, tile_ways, side, &mut tile_ids_changes);
+                    let mut ways_attributes = vec![EdgeAttributes::default(); ways.len()];
+                    for (global_way_id, local_way_id) in &tile_ids_changes {
+                        if let Some(way_attributes) = attributes.get(global_way_id) {
+                            ways_attributes[*local_way_id] = *way_attributes;
+                        }
+                    }
+                    let local_ids_changes = deduplicate_ways(&mut ways, &mut ways_attributes);
+                    let sub_tiles = subdivide_tile(&ways);
+                    let mut new_local_ids = vec![(0, 0); ways.len()];
+                    let tile_start = binary_ways.len();
+                    let mut ends = Vec::new();
+                    // ids of ways after u8::MAX sub-tiles are wrong but such maps
+                    // are refused by save_sub_tiles
+                    for (sub_tile, sub_tile_ways) in sub_tiles.iter().enumerate() {
+                        for (local_way_id, way) in sub_tile_ways.iter().enumerate() {
+                            new_local_ids[*way] = (sub_tile as u8, local_way_id as u8);
+                            binary_ways.extend(ways[*way].iter().flatten().copied());
+                            binary_attributes.push(u8::from(ways_attributes[*way]));
+                        }
+                        ends.push(binary_ways.len() - tile_start);
+                    }
+                    if sub_tiles.len() > 1 {
+                        sub_tiles_ends.insert(tile_id, ends);
+                    }
+                    ids_changes.extend(tile_ids_changes.into_iter().map(
+                        |(global_way_id, old_local_id)| {
+                            let local_id = local_ids_changes
+                                .get(&old_local_id)
+                                .copied()
+                                .unwrap_or(old_local_id);
+                            let (sub_tile, local_way_id) = new_local_ids[local_id];
+                            (
+                                global_way_id,
+                                CWayId {
+                                    tile_number: tile_id as u32,
+                                    sub_tile,
+                                    local_way_id,
+                                },
+                            )
+                        },
+                    ));
                 }
                 tiles_sizes_prefix.push(binary_ways.len());
                 tile_id += 1;
             }
         }
 
-        let new_streets: HashMap<_, _> = streets
-            .into_iter()
-            .map(|(name, street)| {
-                (
-                    name,
+        // names are stored as the watch displays them,
+        // streets with the same displayed name are merged.
+        let mut new_streets: HashMap<String, Vec<CWayId>> = HashMap::new();
+        for (name, street) in streets {
+            new_streets
+                .entry(crate::streets::street_label(&name))
+                .or_default()
+                .extend(
                     street
                         .iter()
-                        .filter_map(|old_id| ids_changes.get(old_id).copied())
-                        .map(|old_local_id| {
-                            local_ids_changes
-                                .get(&old_local_id)
-                                .copied()
-                                .unwrap_or(old_local_id)
-                        })
-                        .collect::<Vec<_>>(),
-                )
-            })
-            .collect();
+                        .filter_map(|old_id| ids_changes.get(old_id).copied()),
+                );
+        }
 
         Map {
             color,
@@ -192,19 +702,41 @@ impl Map {
             first_tile: (xmin, ymin),
             start_coordinates: (xmin as f64 * side, ymin as f64 * side),
             tiles_sizes_prefix,
+            sub_tiles_ends,
             grid_size: ((xmax + 1 - xmin) as usize, (ymax + 1 - ymin) as usize),
             side,
             streets: new_streets,
+            attributes: if attributes.is_empty() {
+                Vec::new()
+            } else {
+                binary_attributes
+            },
         }
     }
 
-    pub fn from_path(mut nodes: Vec<Node>, side: f64) -> Self {
-        let mut ways = vec![(0..nodes.len() as u64).collect::<Vec<_>>()];
+    // breaks are the indices of the nodes starting a new segment
+    pub fn from_path(mut nodes: Vec<Node>, breaks: &[usize], side: f64) -> Self {
+        let mut ways = std::iter::once(0)
+            .chain(breaks.iter().copied())
+            .chain(std::iter::once(nodes.len()))
+            .tuple_windows()
+            .filter(|(start, end)| end - start >= 2)
+            .map(|(start, end)| (start as u64..end as u64).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
         let mut streets = HashMap::new();
+        let mut attributes = HashMap::new();
         crate::cut_segments_on_tiles(&mut nodes, &mut ways, side);
-        let ways = crate::cut_ways_into_edges(ways, &mut streets);
+        let ways = crate::cut_ways_into_edges(ways, &mut streets, &mut attributes);
         let tiles = crate::group_ways_in_tiles(&nodes, &ways, side);
-        Map::new([255, 0, 0], &nodes, &ways, streets, &tiles, side)
+        Map::new(
+            [255, 0, 0],
+            &nodes,
+            &ways,
+            streets,
+            &attributes,
+            &tiles,
+            side,
+        )
     }
 
     pub fn non_empty_tiles(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
@@ -212,35 +744,180 @@ impl Map {
             (0..self.grid_size.1)
                 .map(move |tile_y| (tile_x, tile_y))
                 .filter(|(tile_x, tile_y)| {
-                    self.tile_ways_number((tile_x + tile_y * self.grid_size.0) as u16) > 0
+                    self.tile_ways_number((tile_x + tile_y * self.grid_size.0) as u32) > 0
                 })
         })
     }
 
-    pub fn save_tiles<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
-        writer.write_all(&[BlockType::Tiles as u8])?;
-        writer.write_all(&self.color)?;
+    // save all tiles, compressed by groups if asked so
+    pub fn save_tiles<W: Write>(&self, writer: &mut W, compressed: bool) -> std::io::Result<()> {
+        let block_type = if compressed {
+            BlockType::CompressedTiles
+        } else {
+            BlockType::Tiles
+        };
+        write_block(writer, block_type, |writer| {
+            writer.write_all(&self.color)?;
 
-        // first, the header
-        writer.write_all(&(self.first_tile.0 as i32).to_le_bytes())?;
-        writer.write_all(&(self.first_tile.1 as i32).to_le_bytes())?;
-        writer.write_all(&(self.grid_size.0 as u32).to_le_bytes())?;
-        writer.write_all(&(self.grid_size.1 as u32).to_le_bytes())?;
-        writer.write_all(&self.start_coordinates.0.to_le_bytes())?;
-        writer.write_all(&self.start_coordinates.1.to_le_bytes())?;
-        writer.write_all(&self.side.to_le_bytes())?;
+            // first, the header
+            writer.write_all(&(self.first_tile.0 as i32).to_le_bytes())?;
+            writer.write_all(&(self.first_tile.1 as i32).to_le_bytes())?;
+            writer.write_all(&(self.grid_size.0 as u32).to_le_bytes())?;
+            writer.write_all(&(self.grid_size.1 as u32).to_le_bytes())?;
+            writer.write_all(&self.start_coordinates.0.to_le_bytes())?;
+            writer.write_all(&self.start_coordinates.1.to_le_bytes())?;
+            writer.write_all(&self.side.to_le_bytes())?;
 
-        self.save_sizes_prefix(writer)?;
-        // for s in &self.tiles_sizes_prefix {
-        //     assert!(*s <= 1 << 24);
-        //     writer.write_all(&(*s as u32).to_le_bytes()[0..3]).await?;
-        // }
+            self.save_sizes_prefix(writer)?;
+            self.save_sub_tiles(writer)?;
+            // attributes are optional, a flag tells if we have them
+            writer.write_all(&[!self.attributes.is_empty() as u8])?;
+            writer.write_all(&self.attributes)?;
+            // for s in &self.tiles_sizes_prefix {
+            //     assert!(*s <= 1 << 24);
+            //     writer.write_all(&(*s as u32).to_le_bytes()[0..3]).await?;
+            // }
+
+            if compressed {
+                self.save_compressed_groups(writer)
+            } else {
+                // now, all tiled ways ; size is last element of sizes_prefix
+                writer.write_all(&self.binary_ways)
+            }
+        })
+    }
+
+    // binary of each group of TILES_PER_GROUP consecutive non-empty tiles
+    fn tiles_groups(&self) -> Vec<&[u8]> {
+        let non_empty_tiles = (0..self.tiles_sizes_prefix.len())
+            .filter(|tile_number| self.tile_ways_number(*tile_number as u32) > 0)
+            .collect::<Vec<_>>();
+        non_empty_tiles
+            .chunks(TILES_PER_GROUP)
+            .map(|group| {
+                let start = group[0]
+                    .checked_sub(1)
+                    .map(|i| self.tiles_sizes_prefix[i])
+                    .unwrap_or_default();
+                let end = self.tiles_sizes_prefix[*group.last().unwrap()];
+                &self.binary_ways[start..end]
+            })
+            .collect()
+    }
+
+    // groups size, the end offset of each compressed group and all compressed groups
+    fn save_compressed_groups<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&[TILES_PER_GROUP as u8])?;
+        let compressed_groups = self
+            .tiles_groups()
+            .into_iter()
+            .map(compress)
+            .collect::<Vec<_>>();
+        for end in compressed_groups.iter().scan(0, |end, group| {
+            *end += group.len();
+            Some(*end)
+        }) {
+            writer.write_all(&(end as u32).to_le_bytes())?;
+        }
+        compressed_groups
+            .iter()
+            .try_for_each(|group| writer.write_all(group))
+    }
+
+    // load back a tiles block written by save_tiles (block type and size already read).
+    pub fn load_tiles<R: Read>(reader: &mut R, compressed: bool) -> Result<Self, DecodeError> {
+        let mut color = [0u8; 3];
+        reader.read_exact(&mut color)?;
+
+        let first_tile = (
+            reader.read_i32::<LittleEndian>()? as isize,
+            reader.read_i32::<LittleEndian>()? as isize,
+        );
+        let grid_size = (
+            reader.read_u32::<LittleEndian>()? as usize,
+            reader.read_u32::<LittleEndian>()? as usize,
+        );
+        let start_coordinates = (
+            reader.read_f64::<LittleEndian>()?,
+            reader.read_f64::<LittleEndian>()?,
+        );
+        let side = reader.read_f64::<LittleEndian>()?;
+        let finite =
+            side.is_finite() && start_coordinates.0.is_finite() && start_coordinates.1.is_finite();
+        if !finite || side <= 0. {
+            return Err(DecodeError::InvalidData(format!(
+                "invalid tiles geometry: start {start_coordinates:?}, side {side}"
+            )));
+        }
+        let tiles_number = grid_tiles_number(grid_size)?;
+        if tiles_number == 0 {
+            return Err(DecodeError::BadSize("empty grid".to_owned()));
+        }
 
-        // now, all tiled ways ; size is last element of sizes_prefix
-        writer.write_all(&self.binary_ways)?;
+        let tiles_offsets = TilesOffsets::load(reader, grid_size)?;
+        tiles_offsets.validate(grid_size, 4)?;
+        let sub_tiles_ends = load_sub_tiles(reader, grid_size)?;
+        let attributes = load_attributes(reader, tiles_offsets.entries_number())?;
+        let binary_ways = if compressed {
+            load_compressed_groups(reader, &tiles_offsets)?
+        } else {
+            read_bytes(reader, tiles_offsets.end_offset())?
+        };
+
+        let map = Map {
+            color,
+            binary_ways,
+            start_coordinates,
+            first_tile,
+            tiles_sizes_prefix: tiles_offsets.tiles_sizes_prefix(tiles_number),
+            sub_tiles_ends,
+            grid_size,
+            side,
+            streets: HashMap::new(),
+            attributes,
+        };
+        map.validate_sub_tiles()?;
+        Ok(map)
+    }
+
+    // sub-tiles must exactly cover overcrowded tiles (and only them)
+    fn validate_sub_tiles(&self) -> Result<(), DecodeError> {
+        let tiles_number = self.tiles_sizes_prefix.len();
+        for (tile, ends) in &self.sub_tiles_ends {
+            if *tile >= tiles_number {
+                return Err(DecodeError::TileOutOfRange {
+                    tile: *tile,
+                    tiles_number,
+                });
+            }
+            let tile_size = self.tile_binary(*tile as u32).len();
+            if ends.last() != Some(&tile_size) {
+                return Err(DecodeError::BadSize(format!(
+                    "sub-tiles of tile {tile} do not cover its {tile_size} bytes"
+                )));
+            }
+        }
+        if let Some(tile) = (0..tiles_number).find(|tile| {
+            !self.sub_tiles_ends.contains_key(tile)
+                && self.tile_ways_number(*tile as u32) > MAX_TILE_WAYS
+        }) {
+            return Err(DecodeError::BadSize(format!(
+                "tile {tile} has too many ways and no sub-tiles"
+            )));
+        }
         Ok(())
     }
 
+    // does given way exist in our tiles
+    fn contains_way(&self, way_id: &CWayId) -> bool {
+        (way_id.tile_number as usize) < self.tiles_sizes_prefix.len()
+            && (way_id.sub_tile as usize) < self.sub_tiles_ends(way_id.tile_number).len()
+            && (way_id.local_way_id as usize) * 4
+                < self
+                    .sub_tile_binary(way_id.tile_number, way_id.sub_tile)
+                    .len()
+    }
+
     pub fn save_sizes_prefix<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
         let non_empty_tiles = std::iter::once([0, self.tiles_sizes_prefix[0]].as_slice())
             .chain(self.tiles_sizes_prefix.windows(2))
@@ -248,7 +925,7 @@ impl Map {
             .filter_map(|(i, w)| if w[0] != w[1] { Some(i) } else { None })
             .collect::<Vec<usize>>();
         let bytes_number = if self.tiles_sizes_prefix.last().copied().unwrap_or_default() / 4
-            <= std::u16::MAX as usize
+            <= u16::MAX as usize
         {
             writer.write_all(&[16])?;
             2
@@ -257,19 +934,14 @@ impl Map {
             3
         };
         writer.write_all(&[4])?; // size taken by each way
-        writer.write_all(&(non_empty_tiles.len() as u16).to_le_bytes())?;
-
-        let bytes_per_tile_index = if self.grid_size.0 * self.grid_size.1 > std::u16::MAX as usize {
-            3
-        } else {
-            2
-        };
+        let bytes_per_tile_index = tile_index_size(self.grid_size);
+        writer.write_all(&non_empty_tiles.len().to_le_bytes()[0..bytes_per_tile_index])?;
         for tile in &non_empty_tiles {
             writer.write_all(&tile.to_le_bytes()[0..bytes_per_tile_index])?;
         }
         for end in non_empty_tiles
             .iter()
-            .map(|tile_index| self.tiles_sizes_prefix[*tile_index as usize])
+            .map(|tile_index| self.tiles_sizes_prefix[*tile_index])
             // compute position in ways not in bytes
             .map(|end| {
                 assert_eq!(end % 4, 0);
@@ -281,21 +953,56 @@ impl Map {
         Ok(())
     }
 
-    pub async fn save_streets<W: AsyncWriteExt + std::marker::Unpin>(
-        &self,
-        writer: &mut W,
-    ) -> std::io::Result<()> {
-        writer.write_all(&[BlockType::Streets as u8]).await?;
-
-        // finally, write all streets data
-        let encoded = crate::streets::encode_streets(&self.streets);
-        // let streets_back = crate::streets::decode_streets(&encoded);
-        // self.streets.iter().for_each(|(name, ways)| {
-        //     let restored_ways = streets_back.get(name).unwrap();
-        //     ways.iter().zip(restored_ways).all(|(w1, w2)| w1 == w2);
-        // });
-        writer.write_all(&encoded).await?;
+    // for each overcrowded tile : its number of sub-tiles and the number of ways in each
+    pub fn save_sub_tiles<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let bytes_per_tile_index = tile_index_size(self.grid_size);
+        writer.write_all(&self.sub_tiles_ends.len().to_le_bytes()[0..bytes_per_tile_index])?;
+        for (tile, ends) in self.sub_tiles_ends.iter().sorted() {
+            let sub_tiles_number = u8::try_from(ends.len()).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("tile {tile} has too many ways ({} sub-tiles)", ends.len()),
+                )
+            })?;
+            writer.write_all(&tile.to_le_bytes()[0..bytes_per_tile_index])?;
+            writer.write_all(&[sub_tiles_number])?;
+            for (start, end) in std::iter::once(0)
+                .chain(ends.iter().copied())
+                .tuple_windows()
+            {
+                writer.write_all(&[((end - start) / 4) as u8])?;
+            }
+        }
+        Ok(())
+    }
 
+    pub fn save_streets<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let encoded =
+            crate::streets::encode_streets(&self.streets, tile_index_size(self.grid_size));
+        write_block(writer, BlockType::Streets, |writer| {
+            writer.write_all(&encoded)
+        })
+    }
+
+    // load back a streets block (block type and size already read).
+    pub fn load_streets<R: Read>(&mut self, reader: &mut R) -> Result<(), DecodeError> {
+        let size = reader.read_u32::<LittleEndian>()? as usize;
+        if size < 4 {
+            return Err(DecodeError::BadSize(format!("invalid streets size {size}")));
+        }
+        let mut encoded = (size as u32).to_le_bytes().to_vec();
+        encoded.extend(read_bytes(reader, size - 4)?);
+        let streets = crate::streets::decode_streets(&encoded)?;
+        if let Some(street) = streets
+            .iter()
+            .find(|(_, ways)| ways.iter().any(|way_id| !self.contains_way(way_id)))
+            .map(|(street, _)| street)
+        {
+            return Err(DecodeError::InvalidData(format!(
+                "street {street} references missing ways"
+            )));
+        }
+        self.streets = streets;
         Ok(())
     }
 
@@ -318,7 +1025,7 @@ impl Map {
 
     pub fn ways(&self) -> impl Iterator<Item = [Node; 2]> + '_ {
         (0..self.tiles_sizes_prefix.len())
-            .flat_map(|tile_number| self.tile_ways(tile_number as u16).map(|(_, n)| n))
+            .flat_map(|tile_number| self.tile_ways(tile_number as u32).map(|(_, n, _)| n))
     }
 
     pub fn decompress(&self) -> (Vec<Node>, Vec<Vec<NodeId>>) {
@@ -358,13 +1065,28 @@ impl Map {
             self.binary_ways.len(),
             self.tiles_sizes_prefix.len(),
             (0..self.tiles_sizes_prefix.len())
-                .map(|tile_number| self.tile_ways(tile_number as u16).count())
+                .map(|tile_number| self.tile_ways(tile_number as u32).count())
                 .max()
                 .unwrap(),
         )
     }
 
-    pub fn tile_binary(&self, tile_number: u16) -> &[u8] {
+    // return compressed tiles size in bytes, groups number
+    // and size of the largest decompressed group (what we need to decode to reach any tile)
+    pub fn compression_stats(&self) -> (usize, usize, usize) {
+        let groups = self.tiles_groups();
+        (
+            groups.iter().map(|group| compress(group).len()).sum(),
+            groups.len(),
+            groups
+                .iter()
+                .map(|group| group.len())
+                .max()
+                .unwrap_or_default(),
+        )
+    }
+
+    pub fn tile_binary(&self, tile_number: u32) -> &[u8] {
         let binary_end = self.tiles_sizes_prefix[tile_number as usize];
         let binary_start = tile_number
             .checked_sub(1)
@@ -373,26 +1095,72 @@ impl Map {
         &self.binary_ways[binary_start..binary_end]
     }
 
-    // get number of ways inside given tile
-    pub fn tile_ways_number(&self, tile_number: u16) -> u8 {
-        (self.tile_binary(tile_number).len() / 4) as u8
+    // end offsets (in bytes, from the tile start) of all sub-tiles of given tile
+    pub fn sub_tiles_ends(&self, tile_number: u32) -> Vec<usize> {
+        self.sub_tiles_ends
+            .get(&(tile_number as usize))
+            .cloned()
+            .unwrap_or_else(|| vec![self.tile_binary(tile_number).len()])
     }
 
-    // loop on all ways inside given tile
-    pub fn tile_ways(&self, tile_number: u16) -> impl Iterator<Item = (CWayId, [Node; 2])> + '_ {
-        (0..(self.tile_ways_number(tile_number))).map(move |local_way_id| {
-            let way_id = CWayId {
-                tile_number,
-                local_way_id,
-            };
-            (way_id, self.decode_way(way_id))
-        })
+    pub fn sub_tile_binary(&self, tile_number: u32, sub_tile: u8) -> &[u8] {
+        let tile_binary = self.tile_binary(tile_number);
+        match self.sub_tiles_ends.get(&(tile_number as usize)) {
+            Some(ends) => {
+                let start = (sub_tile as usize)
+                    .checked_sub(1)
+                    .map(|i| ends[i])
+                    .unwrap_or_default();
+                &tile_binary[start..ends[sub_tile as usize]]
+            }
+            None => tile_binary,
+        }
+    }
+
+    // get number of ways inside given tile (all sub-tiles included)
+    pub fn tile_ways_number(&self, tile_number: u32) -> usize {
+        self.tile_binary(tile_number).len() / 4
+    }
+
+    // loop on all ways ids inside given tile
+    pub fn tile_ways_ids(&self, tile_number: u32) -> impl Iterator<Item = CWayId> {
+        std::iter::once(0)
+            .chain(self.sub_tiles_ends(tile_number))
+            .tuple_windows()
+            .enumerate()
+            .flat_map(move |(sub_tile, (start, end))| {
+                (0..(end - start) / 4).map(move |local_way_id| CWayId {
+                    tile_number,
+                    sub_tile: sub_tile as u8,
+                    local_way_id: local_way_id as u8,
+                })
+            })
+    }
+
+    // loop on all ways inside given tile, with their attributes if the map has some
+    pub fn tile_ways(
+        &self,
+        tile_number: u32,
+    ) -> impl Iterator<Item = (CWayId, [Node; 2], Option<EdgeAttributes>)> + '_ {
+        self.tile_ways_ids(tile_number)
+            .map(|way_id| (way_id, self.decode_way(way_id), self.way_attributes(way_id)))
+    }
+
+    pub fn way_attributes(&self, way_id: CWayId) -> Option<EdgeAttributes> {
+        if self.attributes.is_empty() {
+            return None;
+        }
+        // ways take two nodes
+        let way_index = self.node_offset_id(&way_id.node(0)) / 2;
+        self.attributes
+            .get(way_index)
+            .and_then(|byte| EdgeAttributes::try_from(*byte).ok())
     }
 
     pub(crate) fn decode_node(&self, node_id: CNodeId) -> Node {
         let tile_x = node_id.tile_number as usize % self.grid_size.0;
         let tile_y = node_id.tile_number as usize / self.grid_size.0;
-        let binary_tile = self.tile_binary(node_id.tile_number);
+        let binary_tile = self.sub_tile_binary(node_id.tile_number, node_id.sub_tile);
         let cx = binary_tile[2 * node_id.local_node_id as usize];
         let cy = binary_tile[2 * node_id.local_node_id as usize + 1];
 
@@ -403,14 +1171,8 @@ impl Map {
 
     fn decode_way(&self, way_id: CWayId) -> [Node; 2] {
         [
-            self.decode_node(CNodeId {
-                tile_number: way_id.tile_number,
-                local_node_id: 2 * way_id.local_way_id as u16,
-            }),
-            self.decode_node(CNodeId {
-                tile_number: way_id.tile_number,
-                local_node_id: 2 * way_id.local_way_id as u16 + 1,
-            }),
+            self.decode_node(way_id.node(0)),
+            self.decode_node(way_id.node(1)),
         ]
     }
 
@@ -427,8 +1189,12 @@ impl Map {
             .checked_sub(1)
             .map(|i| self.tiles_sizes_prefix[i as usize])
             .unwrap_or_default();
-        let offset = tile_offset + 2 * id.local_node_id as usize;
-        assert!(offset % 2 == 0);
+        let sub_tile_offset = (id.sub_tile as usize)
+            .checked_sub(1)
+            .map(|i| self.sub_tiles_ends[&(id.tile_number as usize)][i])
+            .unwrap_or_default();
+        let offset = tile_offset + sub_tile_offset + 2 * id.local_node_id as usize;
+        assert!(offset.is_multiple_of(2));
         offset / 2
     }
 
@@ -458,8 +1224,24 @@ impl Map {
             }
         }
 
+        // tiles are renumbered, so streets must follow
+        let old_width = self.grid_size.0;
+        let new_width = xmax + 1 - xmin;
+        let renumber = |tile_number: usize| {
+            let x = tile_number % old_width;
+            let y = tile_number / old_width;
+            x - xmin + (y - ymin) * new_width
+        };
+        for way_id in self.streets.values_mut().flatten() {
+            way_id.tile_number = renumber(way_id.tile_number as usize) as u32;
+        }
+        self.sub_tiles_ends = std::mem::take(&mut self.sub_tiles_ends)
+            .into_iter()
+            .map(|(tile_number, ends)| (renumber(tile_number), ends))
+            .collect();
+
         self.tiles_sizes_prefix = new_prefix;
-        self.grid_size = (xmax + 1 - xmin, ymax + 1 - ymin);
+        self.grid_size = (new_width, ymax + 1 - ymin);
         self.first_tile = (
             self.first_tile.0 + xmin as isize,
             self.first_tile.1 + ymin as isize,
@@ -474,6 +1256,7 @@ impl Map {
     pub fn keep_tiles(&mut self, kept_tiles: &HashSet<(usize, usize)>) {
         let mut new_binary_ways: Vec<u8> = Vec::new();
         let mut new_tiles_sizes_prefix = Vec::new();
+        let mut new_attributes = Vec::new();
         let mut kept_ways = HashSet::new();
         let mut current_end = 0;
         for (tile_number, (tile_start, tile_end)) in std::iter::once(0)
@@ -485,16 +1268,21 @@ impl Map {
             let tile_y = tile_number / self.grid_size.0;
             if kept_tiles.contains(&(tile_x, tile_y)) {
                 new_binary_ways.extend(&self.binary_ways[tile_start..tile_end]);
-                kept_ways.extend((0..(tile_end - tile_start) / 2).map(|local_way_id| CWayId {
-                    tile_number: tile_number as u16,
-                    local_way_id: local_way_id as u8,
-                }));
+                if !self.attributes.is_empty() {
+                    new_attributes.extend(&self.attributes[tile_start / 4..tile_end / 4]);
+                }
+                kept_ways.extend(self.tile_ways_ids(tile_number as u32));
                 current_end += tile_end - tile_start;
             }
             new_tiles_sizes_prefix.push(current_end);
         }
         self.binary_ways = new_binary_ways;
+        self.attributes = new_attributes;
         self.tiles_sizes_prefix = new_tiles_sizes_prefix;
+        let grid_width = self.grid_size.0;
+        self.sub_tiles_ends.retain(|tile_number, _| {
+            kept_tiles.contains(&(tile_number % grid_width, tile_number / grid_width))
+        });
         // now filter streets
         self.streets.retain(|_, street_ways| {
             street_ways.retain(|way_id| kept_ways.contains(way_id));
@@ -503,25 +1291,37 @@ impl Map {
     }
 }
 
+// merge overlapping aligned ways and return the new position of each merged way.
+// only ways with the same attributes are merged.
+// ways end up oriented from their smallest point so their attributes follow.
 fn deduplicate_ways(
     ways: &mut Vec<[[u8; 2]; 2]>,
-    tile_id: usize,
-    local_ids_changes: &mut HashMap<CWayId, CWayId>,
-) {
+    attributes: &mut Vec<EdgeAttributes>,
+) -> HashMap<usize, usize> {
     use rational::Rational;
     let mut lines: HashMap<_, [Vec<_>; 2]> = HashMap::new();
-    for (local_way_num, [start, end]) in ways.iter().copied().enumerate() {
+    for (local_way_num, ([start, end], way_attributes)) in ways
+        .iter()
+        .copied()
+        .zip(attributes.iter().copied())
+        .enumerate()
+    {
         let [x1, y1] = start;
         let [x2, y2] = end;
         // assert!(x1 != x2 || y1 != y2);
         let key = if x1 == x2 {
             (Rational::new(256, 1), Rational::new(x1, 1))
         } else {
-            let slope = Rational::new(y2 - y1, x2 - x1);
+            let slope = Rational::new(y2 as i16 - y1 as i16, x2 as i16 - x1 as i16);
             let height = y1 - slope * x1;
             (slope, height)
         };
-        let points = lines.entry(key).or_default();
+        let way_attributes = if start < end {
+            way_attributes
+        } else {
+            way_attributes.reversed()
+        };
+        let points = lines.entry((key, way_attributes)).or_default();
         if start < end {
             points[0].push((start, local_way_num));
             points[1].push((end, local_way_num));
@@ -531,7 +1331,9 @@ fn deduplicate_ways(
         }
     }
     let mut remaining_ways = Vec::new();
-    for [starts, ends] in lines.into_values() {
+    let mut remaining_attributes = Vec::new();
+    let mut local_ids_changes = HashMap::new();
+    for ((_, line_attributes), [starts, ends]) in lines {
         let mut events: HashMap<_, [Vec<usize>; 2]> = HashMap::new();
         for (start, way_num) in starts {
             events.entry(start).or_default()[0].push(way_num);
@@ -556,17 +1358,11 @@ fn deduplicate_ways(
                 current_start = Some(point);
             } else if current_count > 0 && new_count == 0 {
                 // new way end
-                let new_way_id = CWayId {
-                    tile_number: tile_id as u16,
-                    local_way_id: remaining_ways.len() as u8,
-                };
+                let new_way_num = remaining_ways.len();
                 remaining_ways.push([current_start.unwrap(), point]);
+                remaining_attributes.push(line_attributes);
                 for way_num in inner_ways.drain(..) {
-                    let old_way_id = CWayId {
-                        tile_number: tile_id as u16,
-                        local_way_id: way_num as u8,
-                    };
-                    local_ids_changes.insert(old_way_id, new_way_id);
+                    local_ids_changes.insert(way_num, new_way_num);
                 }
             }
             for start in starts {
@@ -575,6 +1371,56 @@ fn deduplicate_ways(
         }
     }
     std::mem::swap(ways, &mut remaining_ways);
+    std::mem::swap(attributes, &mut remaining_attributes);
+    local_ids_changes
+}
+
+// local way ids are stored on one byte
+pub const MAX_TILE_WAYS: usize = u8::MAX as usize;
+
+// split the ways of an overcrowded tile into sub-tiles of at most MAX_TILE_WAYS ways.
+// we split recursively into quadrants, each way going into the quadrant of its middle.
+// ways keep their coordinates inside the whole tile.
+// returns the ways positions for each sub-tile.
+// there can be more than u8::MAX sub-tiles, in which case the map cannot be saved.
+fn subdivide_tile(ways: &[[[u8; 2]; 2]]) -> Vec<Vec<usize>> {
+    fn split(
+        ways: &[[[u8; 2]; 2]],
+        positions: Vec<usize>,
+        (xmin, ymin): (u16, u16),
+        size: u16,
+        sub_tiles: &mut Vec<Vec<usize>>,
+    ) {
+        if positions.len() <= MAX_TILE_WAYS {
+            if !positions.is_empty() {
+                sub_tiles.push(positions);
+            }
+        } else if size == 1 {
+            // all middles in the same cell, just cut
+            sub_tiles.extend(positions.chunks(MAX_TILE_WAYS).map(|c| c.to_vec()));
+        } else {
+            let half = size / 2;
+            let mut quadrants: [Vec<usize>; 4] = Default::default();
+            for position in positions {
+                let [[x1, y1], [x2, y2]] = ways[position];
+                let middle_x = (x1 as u16 + x2 as u16) / 2;
+                let middle_y = (y1 as u16 + y2 as u16) / 2;
+                let quadrant =
+                    (middle_x >= xmin + half) as usize + 2 * (middle_y >= ymin + half) as usize;
+                quadrants[quadrant].push(position);
+            }
+            for (quadrant, positions) in quadrants.into_iter().enumerate() {
+                let corner = (
+                    xmin + (quadrant % 2) as u16 * half,
+                    ymin + (quadrant / 2) as u16 * half,
+                );
+                split(ways, positions, corner, half, sub_tiles);
+            }
+        }
+    }
+    let mut sub_tiles = Vec::new();
+    split(ways, (0..ways.len()).collect(), (0, 0), 256, &mut sub_tiles);
+    sub_tiles
 }
 
 fn compress_tile(
@@ -584,8 +1430,7 @@ fn compress_tile(
     tile_y: isize,
     tile_ways: &[WayId],
     side: f64,
-    ids_changes: &mut HashMap<WayId, CWayId>,
-    tile_id: usize,
+    ids_changes: &mut HashMap<WayId, usize>,
 ) -> Vec<[[u8; 2]; 2]> {
     let mut compressed_ways = Vec::new();
 
@@ -636,7 +1481,7 @@ fn compress_tile(
     //     }
     //     todo!()
     // }
-    for (local_way_id, global_way_id) in tile_ways.iter().enumerate() {
+    for global_way_id in tile_ways {
         let mut new_way = Vec::new();
         for node in ways[*global_way_id as usize]
             .iter()
@@ -645,17 +1490,12 @@ fn compress_tile(
             let new_node = node.encode(tile_x, tile_y, side);
             new_way.push(new_node);
         }
-        ids_changes.insert(
-            *global_way_id,
-            CWayId {
-                tile_number: tile_id as u16,
-                local_way_id: local_way_id as u8,
-            },
-        );
         // if tile_id == 1754 {
         //     eprintln!("way: {new_way:?}");
         // }
         if new_way[0][0] != new_way[1][0] || new_way[0][1] != new_way[1][1] {
+            // local ids are positions in the compressed ways
+            ids_changes.insert(*global_way_id, compressed_ways.len());
             compressed_ways.push([new_way[0], new_way[1]]);
         }
     }
@@ -0,0 +1,68 @@
+// human readable informations about the route and the data in the file.
+use std::io::{Read, Write};
+
+use itertools::Itertools;
+use time::format_description::well_known::Rfc3339;
+
+use crate::{
+    decoder::DecodeError,
+    map::{write_block, BlockType},
+    Node,
+};
+
+pub const OSM_ATTRIBUTION: &str = "Map data © OpenStreetMap contributors, ODbL 1.0";
+
+// one "key=value" line per entry
+pub fn save_metadata<W: Write>(
+    metadata: &[(String, String)],
+    writer: &mut W,
+) -> std::io::Result<()> {
+    write_block(writer, BlockType::Metadata, |writer| {
+        for (key, value) in metadata {
+            writeln!(writer, "{key}={}", value.replace('\n', " "))?;
+        }
+        Ok(())
+    })
+}
+
+// load back metadata saved by save_metadata (block type and size already read).
+pub fn load_metadata<R: Read>(reader: &mut R) -> Result<Vec<(String, String)>, DecodeError> {
+    let mut content = Vec::new();
+    reader.read_to_end(&mut content)?;
+    let content = String::from_utf8(content)
+        .map_err(|e| DecodeError::InvalidData(format!("invalid metadata: {e}")))?;
+    content
+        .lines()
+        .map(|line| {
+            line.split_once('=')
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .ok_or_else(|| DecodeError::InvalidData(format!("invalid metadata line {line}")))
+        })
+        .collect()
+}
+
+// path length in meters
+pub fn path_length(path: &[Node]) -> f64 {
+    path.iter()
+        .tuple_windows()
+        .map(|(p1, p2)| p1.exact_meters_to(p2))
+        .sum()
+}
+
+// sum of all climbs in meters
+pub fn elevation_gain(heights: &[f64]) -> f64 {
+    heights
+        .iter()
+        .tuple_windows()
+        .map(|(h1, h2)| (h2 - h1).max(0.))
+        .sum()
+}
+
+// current utc time as YYYY-MM-DDTHH:MM:SSZ
+pub fn creation_time() -> String {
+    time::OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .ok()
+        .and_then(|now| now.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
@@ -1,209 +1,611 @@
 use itertools::Itertools;
-use std::collections::{HashMap, HashSet};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    future::Future,
+    io::Read,
+    path::PathBuf,
+    time::Duration,
+};
 use xml::{reader::XmlEvent, EventReader};
 
-use crate::{Node, NodeId, WayId};
+use crate::{interests::InterestDetails, EdgeAttributes, Node, NodeId, Profile, WayId};
 
-pub async fn request(polygon: &[Node], ski: bool) -> Result<String, Box<dyn std::error::Error>> {
-    let polygon_string: String = polygon
+pub const DEFAULT_OVERPASS_ENDPOINT: &str = "https://overpass-api.de/api/interpreter";
+pub const DEFAULT_USER_AGENT: &str = concat!("gps/", env!("CARGO_PKG_VERSION"));
+
+// anything able to answer an overpass query with osm xml
+pub trait OsmSource {
+    fn answer(&self, query: &str) -> impl Future<Output = Result<String, Box<dyn Error>>>;
+}
+
+// an overpass server, reached over http
+#[derive(Debug, Clone)]
+pub struct Overpass {
+    pub endpoint: String,
+    pub user_agent: String,
+    // not supported by the browser's fetch, ignored in wasm
+    pub timeout: Option<Duration>,
+}
+
+impl Default for Overpass {
+    fn default() -> Self {
+        Overpass {
+            endpoint: DEFAULT_OVERPASS_ENDPOINT.to_owned(),
+            user_agent: DEFAULT_USER_AGENT.to_owned(),
+            timeout: None,
+        }
+    }
+}
+
+impl OsmSource for Overpass {
+    async fn answer(&self, query: &str) -> Result<String, Box<dyn Error>> {
+        let builder = reqwest::Client::builder().user_agent(&self.user_agent);
+        #[cfg(not(target_arch = "wasm32"))]
+        let builder = if let Some(timeout) = self.timeout {
+            builder.timeout(timeout)
+        } else {
+            builder
+        };
+        let client = builder.build()?;
+        let response = client
+            .post(&self.endpoint)
+            .body(query.to_owned())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(response.text().await?)
+    }
+}
+
+// a previously saved answer, whatever the query
+#[derive(Debug, Clone)]
+pub struct OsmFile(pub PathBuf);
+
+impl OsmSource for OsmFile {
+    async fn answer(&self, _query: &str) -> Result<String, Box<dyn Error>> {
+        Ok(std::fs::read_to_string(&self.0)?)
+    }
+}
+
+// closures can stand in for a server
+impl<F: Fn(&str) -> Result<String, Box<dyn Error>>> OsmSource for F {
+    async fn answer(&self, query: &str) -> Result<String, Box<dyn Error>> {
+        self(query)
+    }
+}
+
+// overpass only filters by simple polygons so each statement is repeated for each polygon.
+pub fn overpass_query(
+    polygons: &[Vec<Node>],
+    profile: Profile,
+    key_values: &[(String, String)],
+) -> String {
+    let polygons_strings = polygons
         .iter()
-        .flat_map(|n| [n.y, n.x])
-        .inspect(|c| assert!(!c.is_nan()))
-        .join(" ");
-    let query = if ski {
-        format!(
-            "(
-            way[\"piste:type\"=\"downhill\"](poly:\"{polygon_string}\");
-            >;
-            way[\"aerialway\"](poly:\"{polygon_string}\");
-            >;
-            node(poly:\"{polygon_string}\");
-            );
-            out body;"
-        )
+        .map(|polygon| {
+            polygon
+                .iter()
+                .flat_map(|n| [n.y, n.x])
+                .inspect(|c| assert!(!c.is_nan()))
+                .join(" ")
+        })
+        .collect::<Vec<String>>();
+    let ways = profile
+        .overpass_selectors()
+        .iter()
+        .cartesian_product(&polygons_strings)
+        .map(|(selector, polygon_string)| format!("{selector}(poly:\"{polygon_string}\");\n>;\n"))
+        .join("");
+    // interests can also be buildings or multipolygons
+    let interests = key_values
+        .iter()
+        .filter(|(key, value)| !key.is_empty() && !value.is_empty())
+        .flat_map(|(key, value)| {
+            ["way", "rel"].map(|kind| format!("{kind}[\"{key}\"=\"{value}\"]"))
+        })
+        .cartesian_product(&polygons_strings)
+        .map(|(selector, polygon_string)| format!("{selector}(poly:\"{polygon_string}\");\n>;\n"))
+        .join("");
+    let nodes = polygons_strings
+        .iter()
+        .map(|polygon_string| format!("node(poly:\"{polygon_string}\");\n"))
+        .join("");
+    // routes are asked after the ways they contain, without recursing on their members
+    let routes = profile
+        .overpass_routes_selector()
+        .map(|selector| format!("\n{selector};\nout body;"))
+        .unwrap_or_default();
+    format!("(\n{ways}{interests}{nodes});\nout body;{routes}")
+}
+
+pub async fn request<S: OsmSource>(
+    polygons: &[Vec<Node>],
+    profile: Profile,
+    key_values: &[(String, String)],
+    source: &S,
+) -> Result<String, Box<dyn Error>> {
+    let query = overpass_query(polygons, profile, key_values);
+    eprintln!("request: {polygons:?}");
+    source.answer(&query).await
+}
+
+// piste index is difficulty, aerialways come last
+pub(crate) fn piste(tags: &HashMap<&str, &str>) -> Option<usize> {
+    if tags.contains_key("aerialway") {
+        return Some(4);
+    }
+    match *tags.get("piste:difficulty")? {
+        "novice" => Some(0),
+        "easy" => Some(1),
+        "intermediate" => Some(2),
+        "advanced" => Some(3),
+        _ => None,
+    }
+}
+
+// a hiking or cycling route relation, restricted to the ways we kept
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub kind: String, // osm "route" tag (hiking, bicycle...)
+    pub network: Option<String>,
+    pub reference: Option<String>,
+    pub name: Option<String>,
+    pub ways: Vec<WayId>,
+}
+
+impl Route {
+    pub(crate) fn new(tags: &HashMap<&str, &str>, members: Vec<WayId>) -> Self {
+        let tag = |key| tags.get(key).map(|value| value.to_string());
+        Route {
+            kind: tag("route").unwrap_or_default(),
+            network: tag("network"),
+            reference: tag("ref"),
+            name: tag("name"),
+            ways: members,
+        }
+    }
+
+    // what we display along the path ("GR5" rather than its full name)
+    pub fn label(&self) -> Option<&str> {
+        self.reference.as_deref().or(self.name.as_deref())
+    }
+}
+
+// only keep routes members we have (once)
+pub(crate) fn restrict_routes(routes: &mut Vec<Route>, ways: &HashMap<WayId, Vec<NodeId>>) {
+    for route in routes.iter_mut() {
+        let mut seen = HashSet::new();
+        route
+            .ways
+            .retain(|id| ways.contains_key(id) && seen.insert(*id));
+    }
+    routes.retain(|route| !route.ways.is_empty());
+}
+
+// interest type (index in key_values, starting at 1) of given tags
+pub(crate) fn interest_type(
+    tags: &HashMap<&str, &str>,
+    key_values: &[(String, String)],
+) -> Option<usize> {
+    key_values
+        .iter()
+        .position(|(key, value)| tags.get(key.as_str()) == Some(&value.as_str()))
+        .map(|i| i + 1)
+}
+
+// we keep all tags of interests, the ones we save are chosen later
+pub(crate) fn interest_details(tags: &HashMap<&str, &str>) -> InterestDetails {
+    tags.iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect()
+}
+
+// ways drawing the outline of an interest relation (given with their outer role):
+// the outer ways of multipolygons, all member ways otherwise.
+pub(crate) fn outline_ways(members: &[(WayId, bool)]) -> Vec<WayId> {
+    let outer = members
+        .iter()
+        .filter(|(_, outer)| *outer)
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    if outer.is_empty() {
+        members.iter().map(|(id, _)| *id).collect()
     } else {
-        let wanted_ways = "way[\"highway\"][\"highway\"!=\"motorway\"][\"highway\"!=\"trunk\"][\"hightway\"!=\"motorway_link\"][\"highway\"!=\"trunk_link\"][\"footway\"!=\"crossing\"][\"area\"!=\"yes\"]";
-        format!(
-            "(
-        {wanted_ways}(poly:\"{polygon_string}\");
-        >;
-        node(poly:\"{polygon_string}\");
-        );
-        out body;",
-        )
+        outer
+    }
+}
+
+// place interests mapped as ways or relations (given by their ways)
+pub(crate) fn shapes_interests<'a, F: Fn(&WayId) -> Option<&'a Vec<NodeId>>>(
+    shapes: Vec<(usize, Vec<WayId>, InterestDetails)>,
+    nodes: &HashMap<NodeId, Node>,
+    way_nodes: F,
+) -> Vec<(usize, Node, InterestDetails)> {
+    shapes
+        .into_iter()
+        .filter_map(|(interest, shape_ways, details)| {
+            let rings = shape_ways
+                .iter()
+                .filter_map(&way_nodes)
+                .map(|way| way.iter().filter_map(|id| nodes.get(id)).copied().collect())
+                .collect::<Vec<Vec<Node>>>();
+            representative_point(&assemble_rings(rings)).map(|point| (interest, point, details))
+        })
+        .collect()
+}
+
+// join open ways end to end into rings (multipolygons rings can be split in several ways).
+// ways we cannot close stay open.
+fn assemble_rings(ways: Vec<Vec<Node>>) -> Vec<Vec<Node>> {
+    let (mut rings, mut open): (Vec<_>, Vec<_>) = ways
+        .into_iter()
+        .filter(|way| !way.is_empty())
+        .partition(|way| way.first() == way.last());
+    while let Some(mut chain) = open.pop() {
+        while chain.first() != chain.last() {
+            let end = chain.last().copied();
+            let Some(next) = open
+                .iter()
+                .position(|way| way.first() == end.as_ref() || way.last() == end.as_ref())
+            else {
+                break;
+            };
+            let mut way = open.swap_remove(next);
+            if way.first() != end.as_ref() {
+                way.reverse();
+            }
+            chain.extend(way.into_iter().skip(1));
+        }
+        rings.push(chain);
+    }
+    rings
+}
+
+// centroid of the largest closed ring (buildings, multipolygons outer rings)
+// or center of all points if we have no surface
+fn representative_point(rings: &[Vec<Node>]) -> Option<Node> {
+    let surface = |ring: &[Node]| -> Option<(f64, Node)> {
+        if ring.len() < 4 || ring.first() != ring.last() {
+            return None;
+        }
+        // work relative to the first point to keep some precision
+        let origin = ring[0];
+        let (mut area, mut x, mut y) = (0., 0., 0.);
+        for (p1, p2) in ring.iter().tuple_windows() {
+            let (x1, y1, x2, y2) = (
+                p1.x - origin.x,
+                p1.y - origin.y,
+                p2.x - origin.x,
+                p2.y - origin.y,
+            );
+            let cross = x1 * y2 - x2 * y1;
+            area += cross;
+            x += (x1 + x2) * cross;
+            y += (y1 + y2) * cross;
+        }
+        (area != 0.).then(|| {
+            (
+                area.abs(),
+                Node::new(origin.x + x / (3. * area), origin.y + y / (3. * area)),
+            )
+        })
     };
-    eprintln!("request: {polygon_string:?}");
-    let client = reqwest::Client::builder()
-        //.user_agent("osm-geo-mapper")
-        .build()?;
-    let response = client
-        .post("https://overpass-api.de/api/interpreter")
-        .body(query)
-        .send()
-        .await?;
-    let result = response.text().await?;
-    Ok(result)
-}
-
-// return a hash map point id -> point
+    rings
+        .iter()
+        .filter_map(|ring| surface(ring))
+        .max_by(|(a1, _), (a2, _)| a1.total_cmp(a2))
+        .map(|(_, centroid)| centroid)
+        .or_else(|| {
+            let points = rings.iter().flatten().unique().collect::<Vec<_>>();
+            let count = points.len() as f64;
+            (!points.is_empty()).then(|| {
+                Node::new(
+                    points.iter().map(|p| p.x).sum::<f64>() / count,
+                    points.iter().map(|p| p.y).sum::<f64>() / count,
+                )
+            })
+        })
+}
+
+// more entrances than this are linked through the area's center
+const MAX_LINKED_ENTRANCES: usize = 8;
+
+// pedestrian squares and plazas
+pub(crate) fn is_area(tags: &HashMap<&str, &str>) -> bool {
+    tags.get("area") == Some(&"yes") && tags.contains_key("highway")
+}
+
+// areas can be crossed in any direction but their ways only go around them.
+// we keep the outline for display and add straight connectors
+// between entrances (nodes shared with other ways) so we can route across.
+pub(crate) fn connect_areas(
+    nodes: &mut HashMap<NodeId, Node>,
+    ways: &mut HashMap<WayId, Vec<NodeId>>,
+    streets: &mut HashMap<String, Vec<WayId>>,
+    attributes: &mut HashMap<WayId, EdgeAttributes>,
+    areas: &[WayId],
+) {
+    let mut ways_number: HashMap<NodeId, usize> = HashMap::new();
+    for way in ways.values() {
+        for node in way.iter().unique() {
+            *ways_number.entry(*node).or_default() += 1;
+        }
+    }
+    let mut areas_streets: HashMap<WayId, Vec<String>> = HashMap::new();
+    for (name, street_ways) in streets.iter() {
+        for way in street_ways.iter().filter(|way| areas.contains(way)) {
+            areas_streets.entry(*way).or_default().push(name.clone());
+        }
+    }
+    // connectors and centers get ids osm does not use yet
+    let mut new_node_id = NodeId::MAX;
+    let mut new_way_id = WayId::MAX;
+    for area in areas {
+        let Some(outline) = ways.get(area) else {
+            continue;
+        };
+        if outline.len() < 4 || outline.first() != outline.last() {
+            continue;
+        }
+        let entrances = outline[1..]
+            .iter()
+            .filter(|node| ways_number[node] > 1 && nodes.contains_key(node))
+            .copied()
+            .collect::<Vec<_>>();
+        let connectors = if entrances.len() <= MAX_LINKED_ENTRANCES {
+            entrances
+                .iter()
+                .tuple_combinations()
+                .map(|(n1, n2)| vec![*n1, *n2])
+                .collect::<Vec<_>>()
+        } else {
+            let outline_nodes = outline[1..]
+                .iter()
+                .filter_map(|node| nodes.get(node))
+                .collect::<Vec<_>>();
+            let count = outline_nodes.len() as f64;
+            let center = Node::new(
+                outline_nodes.iter().map(|n| n.x).sum::<f64>() / count,
+                outline_nodes.iter().map(|n| n.y).sum::<f64>() / count,
+            );
+            nodes.insert(new_node_id, center);
+            let connectors = entrances
+                .iter()
+                .map(|entrance| vec![*entrance, new_node_id])
+                .collect();
+            new_node_id -= 1;
+            connectors
+        };
+        for connector in connectors {
+            ways.insert(new_way_id, connector);
+            if let Some(area_attributes) = attributes.get(area).copied() {
+                attributes.insert(new_way_id, area_attributes);
+            }
+            for name in areas_streets.get(area).into_iter().flatten() {
+                streets.get_mut(name).unwrap().push(new_way_id);
+            }
+            new_way_id -= 1;
+        }
+    }
+}
+
+// a hash map point id -> point
 // and a hash map way id -> vec of points id in the way
 // and a hash map street name -> Vec of ways ids
-pub fn parse_osm_xml(
-    xml: &str,
-    key_values: &[(String, String)],
-) -> (
+pub type OsmData = (
     HashMap<NodeId, Node>,
     HashMap<WayId, Vec<NodeId>>,
     HashMap<String, Vec<WayId>>,
-    Vec<HashSet<WayId>>, // pistes (index is difficulty)
-    Vec<(usize, Node)>,  // interests (type + node)
-) {
-    let key_values: HashMap<(&String, &String), usize> = key_values
-        .iter()
-        .enumerate()
-        .map(|(i, (key, value))| ((key, value), i + 1))
-        .collect();
+    Vec<HashSet<WayId>>,                 // pistes (index is difficulty)
+    Vec<(usize, Node, InterestDetails)>, // interests (type + node + all tags)
+    Vec<Route>,
+    HashMap<WayId, EdgeAttributes>, // attributes of each way (in the way's direction)
+);
+
+// osm answers can be huge so we stream them (prefer a buffered reader).
+// ways are filtered with given profile.
+// relations come after their ways so we open the answer a second time
+// to get the member ways of interests relations we did not keep.
+pub fn parse_osm_xml<R: Read, F: FnMut() -> std::io::Result<R>>(
+    mut open: F,
+    key_values: &[(String, String)],
+    profile: Profile,
+) -> std::io::Result<OsmData> {
     let mut pistes = vec![HashSet::new(); 5];
-    let parser = EventReader::new(xml.as_bytes());
+    let parser = EventReader::new(open()?);
     let mut current_node = None;
     let mut current_way: Option<(WayId, Vec<NodeId>)> = None;
+    // member ways of the current relation, with their outer role
+    let mut current_relation: Option<Vec<(WayId, bool)>> = None;
+    // tags of the current node, way or relation
+    let mut tags: Vec<(String, String)> = Vec::new();
     let mut nodes = HashMap::new();
     let mut ways = HashMap::new();
     let mut streets: HashMap<String, Vec<WayId>> = HashMap::new();
     let mut interests = Vec::new();
-    let mut current_interest = None;
-    let mut piste = None;
-    let mut footway = false;
-    let mut bicycle = false;
-    let mut discard_way = false;
-    let mut current_street_name = None;
+    let mut routes = Vec::new();
+    let mut areas = Vec::new();
+    let mut attributes = HashMap::new();
+    // ways we do not display but are interests
+    let mut other_ways = HashMap::new();
+    // interests mapped as ways or relations (type + ways + tags)
+    let mut interest_shapes = Vec::new();
     for e in parser {
         match e {
             Ok(XmlEvent::StartElement {
                 name, attributes, ..
             }) => {
-                if name.local_name == "way" {
-                    footway = false;
-                    bicycle = false;
-                    discard_way = false;
-                    current_way = attributes.iter().find_map(|a| {
-                        if a.name.local_name == "id" {
-                            a.value.parse::<u64>().ok().map(|id| (id, Vec::new()))
-                        } else {
-                            None
-                        }
-                    });
-                }
-                if name.local_name == "tag" {
-                    let mut key = None;
-                    let mut value = None;
-                    for attribute in &attributes {
-                        if attribute.name.local_name == "k" {
-                            key = Some(&attribute.value)
-                        } else if attribute.name.local_name == "v" {
-                            value = Some(&attribute.value)
+                let attribute = |wanted: &str| {
+                    attributes
+                        .iter()
+                        .find(|a| a.name.local_name == wanted)
+                        .map(|a| a.value.as_str())
+                };
+                match name.local_name.as_str() {
+                    "way" => {
+                        tags.clear();
+                        current_way = attribute("id")
+                            .and_then(|id| id.parse::<u64>().ok())
+                            .map(|id| (id, Vec::new()));
+                    }
+                    "node" => {
+                        tags.clear();
+                        let lon = attribute("lon").and_then(|lon| lon.parse::<f64>().ok());
+                        let lat = attribute("lat").and_then(|lat| lat.parse::<f64>().ok());
+                        let id = attribute("id").and_then(|id| id.parse::<u64>().ok());
+                        if let (Some(lon), Some(lat), Some(id)) = (lon, lat, id) {
+                            current_node = Some((id, Node::new(lon, lat)))
                         }
                     }
-
-                    if let Some(key) = key {
-                        if let Some(value) = value {
-                            if key == "piste:difficulty" {
-                                piste = match value.as_str() {
-                                    "novice" => Some(0),
-                                    "easy" => Some(1),
-                                    "intermediate" => Some(2),
-                                    "advanced" => Some(3),
-                                    _ => None,
-                                };
-                            }
-                            if key == "aerialway" {
-                                piste = Some(4)
-                            }
-                            if key == "bicycle" && value == "yes" {
-                                bicycle = true;
-                            }
-                            if key == "highway" && value == "footway" {
-                                footway = true;
-                            }
-                            if key == "highway" && (value == "raceway" || value == "steps") {
-                                discard_way = true;
-                            }
-                            if let Some(interest_id) = key_values.get(&(key, value)) {
-                                current_interest = Some(*interest_id);
-                            }
+                    "tag" => {
+                        if let (Some(key), Some(value)) = (attribute("k"), attribute("v")) {
+                            tags.push((key.to_owned(), value.to_owned()));
                         }
                     }
-
-                    if key == Some(&"name".to_owned()) {
-                        current_street_name = value.map(|s| s.to_owned());
+                    "relation" => {
+                        tags.clear();
+                        current_relation = Some(Vec::new());
                     }
-                }
-                if name.local_name == "nd" {
-                    if let Some((_, points)) = current_way.as_mut() {
-                        points.extend(attributes.iter().find_map(|a| {
-                            if a.name.local_name == "ref" {
-                                a.value.parse::<u64>().ok()
-                            } else {
-                                None
+                    "member" => {
+                        if let Some(members) = current_relation.as_mut() {
+                            if attribute("type") == Some("way") {
+                                let outer = attribute("role") == Some("outer");
+                                members.extend(
+                                    attribute("ref")
+                                        .and_then(|id| id.parse::<u64>().ok())
+                                        .map(|id| (id, outer)),
+                                )
                             }
-                        }))
-                    }
-                }
-                if name.local_name == "node" {
-                    current_interest = None;
-                    let mut lon = None;
-                    let mut lat = None;
-                    let mut id = None;
-                    for attribute in &attributes {
-                        if attribute.name.local_name == "lon" {
-                            lon = attribute.value.parse::<f64>().ok();
-                        } else if attribute.name.local_name == "lat" {
-                            lat = attribute.value.parse::<f64>().ok();
-                        } else if attribute.name.local_name == "id" {
-                            id = attribute.value.parse::<u64>().ok();
                         }
                     }
-                    if let Some(lon) = lon {
-                        if let Some(lat) = lat {
-                            if let Some(id) = id {
-                                current_node = Some((id, Node::new(lon, lat)))
-                            }
+                    "nd" => {
+                        if let Some((_, points)) = current_way.as_mut() {
+                            points.extend(attribute("ref").and_then(|id| id.parse::<u64>().ok()))
                         }
                     }
+                    _ => (),
                 }
             }
             Ok(XmlEvent::EndElement { name }) => {
                 if name.local_name == "way" {
                     if let Some((id, way_points)) = current_way.take() {
-                        if !discard_way && (!footway || bicycle) {
-                            // if !discard_way {
+                        let tags = tags
+                            .iter()
+                            .map(|(key, value)| (key.as_str(), value.as_str()))
+                            .collect::<HashMap<_, _>>();
+                        let interest = interest_type(&tags, key_values);
+                        if let Some(interest) = interest {
+                            interest_shapes.push((interest, vec![id], interest_details(&tags)));
+                        }
+                        if profile.keeps(&tags) {
+                            if is_area(&tags) {
+                                areas.push(id);
+                            }
                             ways.insert(id, way_points);
-
-                            if let Some(street_name) = current_street_name.take() {
-                                streets.entry(street_name.to_owned()).or_default().push(id)
+                            attributes.insert(id, EdgeAttributes::from_tags(&tags));
+                            if let Some(street_name) = tags.get("name") {
+                                streets.entry(street_name.to_string()).or_default().push(id)
                             }
-
-                            if let Some(piste) = piste.take() {
+                            if let Some(piste) = piste(&tags) {
                                 pistes[piste].insert(id);
                             }
+                        } else if interest.is_some() {
+                            other_ways.insert(id, way_points);
+                        }
+                    }
+                }
+                if name.local_name == "relation" {
+                    if let Some(members) = current_relation.take() {
+                        let tags = tags
+                            .iter()
+                            .map(|(key, value)| (key.as_str(), value.as_str()))
+                            .collect::<HashMap<_, _>>();
+                        if let Some(interest) = interest_type(&tags, key_values) {
+                            interest_shapes.push((
+                                interest,
+                                outline_ways(&members),
+                                interest_details(&tags),
+                            ));
+                        }
+                        if profile.keeps_route(&tags) {
+                            let members = members.iter().map(|(id, _)| *id).collect();
+                            routes.push(Route::new(&tags, members));
                         }
                     }
                 }
                 if name.local_name == "node" {
                     if let Some((id, node)) = current_node.take() {
+                        let tags = tags
+                            .iter()
+                            .map(|(key, value)| (key.as_str(), value.as_str()))
+                            .collect::<HashMap<_, _>>();
                         nodes.insert(id, node);
-                        if let Some(interest) = current_interest {
-                            interests.push((interest, node));
+                        if let Some(interest) = interest_type(&tags, key_values) {
+                            interests.push((interest, node, interest_details(&tags)));
                         }
                     }
                 }
             }
-            Err(e) => {
-                println!("Error: {e}");
-                break;
+            Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+            _ => {}
+        }
+    }
+    let missing_ways = interest_shapes
+        .iter()
+        .flat_map(|(_, shape_ways, _)| shape_ways)
+        .filter(|id| !ways.contains_key(id) && !other_ways.contains_key(id))
+        .copied()
+        .collect::<HashSet<WayId>>();
+    if !missing_ways.is_empty() {
+        other_ways.extend(xml_ways(open()?, &missing_ways)?);
+    }
+    interests.extend(shapes_interests(interest_shapes, &nodes, |id| {
+        ways.get(id).or_else(|| other_ways.get(id))
+    }));
+    restrict_routes(&mut routes, &ways);
+    connect_areas(&mut nodes, &mut ways, &mut streets, &mut attributes, &areas);
+    Ok((nodes, ways, streets, pistes, interests, routes, attributes))
+}
+
+// nodes of the given ways, read from osm xml
+fn xml_ways<R: Read>(
+    reader: R,
+    wanted: &HashSet<WayId>,
+) -> std::io::Result<HashMap<WayId, Vec<NodeId>>> {
+    let mut ways = HashMap::new();
+    let mut current_way: Option<(WayId, Vec<NodeId>)> = None;
+    for e in EventReader::new(reader) {
+        match e {
+            Ok(XmlEvent::StartElement {
+                name, attributes, ..
+            }) => {
+                let reference = || {
+                    attributes
+                        .iter()
+                        .find(|a| matches!(a.name.local_name.as_str(), "id" | "ref"))
+                        .and_then(|a| a.value.parse::<u64>().ok())
+                };
+                match name.local_name.as_str() {
+                    "way" => {
+                        current_way = reference()
+                            .filter(|id| wanted.contains(id))
+                            .map(|id| (id, Vec::new()))
+                    }
+                    "nd" => {
+                        if let Some((_, points)) = current_way.as_mut() {
+                            points.extend(reference())
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Ok(XmlEvent::EndElement { name }) if name.local_name == "way" => {
+                ways.extend(current_way.take());
             }
+            Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
             _ => {}
         }
     }
-    (nodes, ways, streets, pistes, interests)
+    Ok(ways)
 }
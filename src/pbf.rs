@@ -0,0 +1,235 @@
+// read map data from osm extracts (geofabrik style .osm.pbf files) instead of overpass.
+// we select what the overpass query would and return what parse_osm_xml does.
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::Path,
+};
+
+use itertools::Itertools;
+use osmio::{OSMObj, OSMObjBase, OSMObjectType, OSMReader, ObjId, Relation, Way};
+
+use crate::{
+    osm::{
+        connect_areas, interest_details, interest_type, is_area, outline_ways, piste,
+        restrict_routes, shapes_interests, OsmData, Route,
+    },
+    EdgeAttributes, Node, NodeId, Profile, WayId,
+};
+
+pub fn parse_osm_pbf<P: AsRef<Path>>(
+    path: P,
+    polygons: &[Vec<Node>],
+    key_values: &[(String, String)],
+    profile: Profile,
+) -> io::Result<OsmData> {
+    parse_osm_objects(
+        || osmio::read_pbf(path.as_ref()).map_err(io::Error::other),
+        polygons,
+        key_values,
+        profile,
+    )
+}
+
+// parse objects coming from any osmio reader, keeping only the ones in the polygons.
+// ways come after their nodes and relations after their ways so we need
+// a second pass to get the member ways of interests relations crossing the polygons
+// borders and a last one to get the nodes of ways crossing these borders.
+// ways can also cross the polygons without any node inside so we roughly remember
+// where all other nodes are during the first pass.
+pub fn parse_osm_objects<R: OSMReader, F: FnMut() -> io::Result<R>>(
+    mut open: F,
+    polygons: &[Vec<Node>],
+    key_values: &[(String, String)],
+    profile: Profile,
+) -> io::Result<OsmData> {
+    let mut nodes = HashMap::new();
+    let mut ways = HashMap::new();
+    let mut streets: HashMap<String, Vec<WayId>> = HashMap::new();
+    let mut pistes = vec![HashSet::new(); 5];
+    let mut interests = Vec::new();
+    let mut routes = Vec::new();
+    let mut areas = Vec::new();
+    let mut attributes = HashMap::new();
+    // interests mapped as ways or relations (type + ways + tags)
+    let mut interest_shapes = Vec::new();
+    let mut other_ways = HashMap::new();
+    // single precision is enough to tell if a segment crosses a polygon
+    let mut outside_nodes: HashMap<NodeId, (f32, f32)> = HashMap::new();
+
+    let mut reader = open()?;
+    for object in reader.objects() {
+        if let Some(node) = object.as_node() {
+            let Some((lat, lon)) = osmio::Node::lat_lon_f64(node) else {
+                continue;
+            };
+            let point = Node::new(lon, lat);
+            if !polygons.iter().any(|polygon| is_inside(&point, polygon)) {
+                outside_nodes.insert(node.id() as NodeId, (lon as f32, lat as f32));
+                continue;
+            }
+            nodes.insert(node.id() as NodeId, point);
+            let tags = node.tags().collect::<HashMap<_, _>>();
+            if let Some(interest) = interest_type(&tags, key_values) {
+                interests.push((interest, point, interest_details(&tags)));
+            }
+        } else if let Some(way) = object.as_way() {
+            let tags = way.tags().collect::<HashMap<_, _>>();
+            let inside = way
+                .nodes()
+                .iter()
+                .any(|id| nodes.contains_key(&(*id as NodeId)));
+            if !inside && !crosses_polygons(way.nodes(), &outside_nodes, polygons) {
+                continue;
+            }
+            let id = way.id() as WayId;
+            let way_nodes = way
+                .nodes()
+                .iter()
+                .map(|id| *id as NodeId)
+                .collect::<Vec<_>>();
+            if let Some(interest) = interest_type(&tags, key_values) {
+                interest_shapes.push((interest, vec![id], interest_details(&tags)));
+            }
+            if !profile.keeps(&tags) {
+                other_ways.insert(id, way_nodes);
+                continue;
+            }
+            if is_area(&tags) {
+                areas.push(id);
+            }
+            ways.insert(id, way_nodes);
+            attributes.insert(id, EdgeAttributes::from_tags(&tags));
+            if let Some(name) = tags.get("name") {
+                streets.entry(name.to_string()).or_default().push(id);
+            }
+            if let Some(piste) = piste(&tags) {
+                pistes[piste].insert(id);
+            }
+        } else if let Some(relation) = object.as_relation() {
+            let tags = relation.tags().collect::<HashMap<_, _>>();
+            let interest = interest_type(&tags, key_values);
+            if interest.is_none() && !profile.keeps_route(&tags) {
+                continue;
+            }
+            let members = relation
+                .members()
+                .filter(|(object_type, _, _)| *object_type == OSMObjectType::Way)
+                .map(|(_, id, role)| (id as WayId, role == "outer"))
+                .collect::<Vec<_>>();
+            let outline = outline_ways(&members);
+            let members = members.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+            // interests need at least one member way in the polygons
+            if let Some(interest) = interest.filter(|_| {
+                outline
+                    .iter()
+                    .any(|id| ways.contains_key(id) || other_ways.contains_key(id))
+            }) {
+                interest_shapes.push((interest, outline, interest_details(&tags)));
+            }
+            if profile.keeps_route(&tags) {
+                routes.push(Route::new(&tags, members));
+            }
+        }
+    }
+    drop(outside_nodes);
+    restrict_routes(&mut routes, &ways);
+    // fetch member ways of interests which are outside the polygons
+    let missing_ways = interest_shapes
+        .iter()
+        .flat_map(|(_, shape_ways, _)| shape_ways)
+        .filter(|id| !ways.contains_key(id) && !other_ways.contains_key(id))
+        .copied()
+        .collect::<HashSet<WayId>>();
+    if !missing_ways.is_empty() {
+        let mut reader = open()?;
+        for way in reader.ways() {
+            let id = way.id() as WayId;
+            if missing_ways.contains(&id) {
+                other_ways.insert(id, way.nodes().iter().map(|id| *id as NodeId).collect());
+            }
+        }
+    }
+    // we only need to remember the ways of interests
+    let interest_ways = interest_shapes
+        .iter()
+        .flat_map(|(_, shape_ways, _)| shape_ways)
+        .collect::<HashSet<_>>();
+    other_ways.retain(|id, _| interest_ways.contains(id));
+
+    // only keep nodes we use, and fetch the ones outside the polygons
+    let used = ways
+        .values()
+        .chain(other_ways.values())
+        .flatten()
+        .copied()
+        .collect::<HashSet<NodeId>>();
+    nodes.retain(|id, _| used.contains(id));
+    if used.len() != nodes.len() {
+        let mut reader = open()?;
+        for node in reader.nodes() {
+            let id = node.id() as NodeId;
+            if used.contains(&id) && !nodes.contains_key(&id) {
+                if let Some((lat, lon)) = osmio::Node::lat_lon_f64(&node) {
+                    nodes.insert(id, Node::new(lon, lat));
+                }
+            }
+        }
+    }
+    interests.extend(shapes_interests(interest_shapes, &nodes, |id| {
+        ways.get(id).or_else(|| other_ways.get(id))
+    }));
+    let used = ways.values().flatten().collect::<HashSet<_>>();
+    nodes.retain(|id, _| used.contains(id));
+    connect_areas(&mut nodes, &mut ways, &mut streets, &mut attributes, &areas);
+    Ok((nodes, ways, streets, pistes, interests, routes, attributes))
+}
+
+// does any segment between the given (outside) nodes cross a polygon border
+fn crosses_polygons(
+    way_nodes: &[ObjId],
+    outside_nodes: &HashMap<NodeId, (f32, f32)>,
+    polygons: &[Vec<Node>],
+) -> bool {
+    way_nodes
+        .iter()
+        .filter_map(|id| outside_nodes.get(&(*id as NodeId)))
+        .map(|(x, y)| Node::new(*x as f64, *y as f64))
+        .tuple_windows()
+        .any(|(a, b)| {
+            polygons.iter().any(|polygon| {
+                polygon
+                    .iter()
+                    .zip(polygon.iter().cycle().skip(1))
+                    .any(|(c, d)| segments_cross(&a, &b, c, d))
+            })
+        })
+}
+
+fn segments_cross(a: &Node, b: &Node, c: &Node, d: &Node) -> bool {
+    // which side of the line p1 p2 is p3 on
+    let side = |p1: &Node, p2: &Node, p3: &Node| {
+        ((p2.x - p1.x) * (p3.y - p1.y) - (p2.y - p1.y) * (p3.x - p1.x)).signum()
+    };
+    // cheap rejection first, polygons have many borders
+    a.x.max(b.x) >= c.x.min(d.x)
+        && c.x.max(d.x) >= a.x.min(b.x)
+        && a.y.max(b.y) >= c.y.min(d.y)
+        && c.y.max(d.y) >= a.y.min(b.y)
+        && side(a, b, c) != side(a, b, d)
+        && side(c, d, a) != side(c, d, b)
+}
+
+// even-odd rule
+fn is_inside(point: &Node, polygon: &[Node]) -> bool {
+    polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(1))
+        .filter(|(a, b)| {
+            (a.y > point.y) != (b.y > point.y)
+                && point.x < a.x + (point.y - a.y) / (b.y - a.y) * (b.x - a.x)
+        })
+        .count()
+        % 2
+        == 1
+}
@@ -0,0 +1,220 @@
+// which ways we want on the map, depending on the activity.
+// the same rules generate the overpass query and filter the parsed ways
+// so that both stay consistent.
+use std::collections::HashMap;
+
+use itertools::Itertools;
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Profile {
+    RoadCycling,
+    // our historical filter: everything a bicycle can ride on
+    #[default]
+    Gravel,
+    Hiking,
+    Running,
+    Ski,
+}
+
+// a tag with one of the given values (any value if none given)
+#[derive(Debug, Clone, Copy)]
+pub struct TagRule {
+    pub key: &'static str,
+    pub values: &'static [&'static str],
+}
+
+// ways with the tag are discarded, unless they also have the exception tag
+#[derive(Debug, Clone, Copy)]
+pub struct Exclusion {
+    pub tag: TagRule,
+    pub unless: Option<TagRule>,
+}
+
+const fn any(key: &'static str) -> TagRule {
+    TagRule { key, values: &[] }
+}
+
+const fn one_of(key: &'static str, values: &'static [&'static str]) -> TagRule {
+    TagRule { key, values }
+}
+
+const fn exclude(tag: TagRule) -> Exclusion {
+    Exclusion { tag, unless: None }
+}
+
+const HIGHWAYS: &[TagRule] = &[any("highway")];
+const PISTES: &[TagRule] = &[one_of("piste:type", &["downhill"]), any("aerialway")];
+
+const MOTORWAYS: Exclusion = exclude(one_of(
+    "highway",
+    &[
+        "motorway",
+        "trunk",
+        "motorway_link",
+        "trunk_link",
+        "raceway",
+    ],
+));
+const CROSSINGS: Exclusion = exclude(one_of("footway", &["crossing"]));
+const STEPS: Exclusion = exclude(one_of("highway", &["steps"]));
+const FOOTWAYS: Exclusion = Exclusion {
+    tag: one_of("highway", &["footway"]),
+    unless: Some(one_of("bicycle", &["yes"])),
+};
+
+pub(crate) const UNPAVED_SURFACES: &[&str] = &[
+    "unpaved",
+    "gravel",
+    "fine_gravel",
+    "compacted",
+    "dirt",
+    "earth",
+    "ground",
+    "grass",
+    "mud",
+    "sand",
+    "pebblestone",
+];
+
+const GRAVEL: &[Exclusion] = &[MOTORWAYS, STEPS, CROSSINGS, FOOTWAYS];
+const ROAD_CYCLING: &[Exclusion] = &[
+    MOTORWAYS,
+    STEPS,
+    CROSSINGS,
+    FOOTWAYS,
+    exclude(one_of("highway", &["track", "path", "bridleway"])),
+    exclude(one_of("surface", UNPAVED_SURFACES)),
+    exclude(one_of("service", &["parking_aisle"])),
+];
+const HIKING: &[Exclusion] = &[MOTORWAYS, CROSSINGS];
+const RUNNING: &[Exclusion] = &[
+    MOTORWAYS,
+    CROSSINGS,
+    exclude(one_of(
+        "sac_scale",
+        &[
+            "demanding_mountain_hiking",
+            "alpine_hiking",
+            "demanding_alpine_hiking",
+            "difficult_alpine_hiking",
+        ],
+    )),
+];
+
+impl TagRule {
+    pub fn matches(&self, tags: &HashMap<&str, &str>) -> bool {
+        tags.get(self.key)
+            .is_some_and(|value| self.values.is_empty() || self.values.contains(value))
+    }
+
+    // overpass filter selecting ways with (or without) the tag
+    fn overpass_filter(&self, negated: bool) -> String {
+        let key = self.key;
+        match (self.values, negated) {
+            ([], false) => format!("[\"{key}\"]"),
+            ([], true) => format!("[!\"{key}\"]"),
+            ([value], false) => format!("[\"{key}\"=\"{value}\"]"),
+            ([value], true) => format!("[\"{key}\"!=\"{value}\"]"),
+            (values, negated) => format!(
+                "[\"{key}\"{}\"^({})$\"]",
+                if negated { "!~" } else { "~" },
+                values.iter().join("|")
+            ),
+        }
+    }
+}
+
+impl Profile {
+    // a way needs one of these tags
+    pub fn included(self) -> &'static [TagRule] {
+        if self == Profile::Ski {
+            PISTES
+        } else {
+            HIGHWAYS
+        }
+    }
+
+    pub fn excluded(self) -> &'static [Exclusion] {
+        match self {
+            Profile::RoadCycling => ROAD_CYCLING,
+            Profile::Gravel => GRAVEL,
+            Profile::Hiking => HIKING,
+            Profile::Running => RUNNING,
+            Profile::Ski => &[],
+        }
+    }
+
+    // kinds of route relations we follow (osm "route" tag)
+    pub fn routes(self) -> &'static [&'static str] {
+        match self {
+            Profile::RoadCycling => &["bicycle"],
+            Profile::Gravel => &["bicycle", "mtb"],
+            Profile::Hiking | Profile::Running => &["hiking", "foot"],
+            Profile::Ski => &[],
+        }
+    }
+
+    pub fn keeps_route(self, tags: &HashMap<&str, &str>) -> bool {
+        tags.get("type") == Some(&"route")
+            && tags
+                .get("route")
+                .is_some_and(|route| self.routes().contains(route))
+    }
+
+    pub fn keeps(self, tags: &HashMap<&str, &str>) -> bool {
+        self.included().iter().any(|rule| rule.matches(tags))
+            && self.excluded().iter().all(|exclusion| {
+                !exclusion.tag.matches(tags)
+                    || exclusion.unless.is_some_and(|unless| unless.matches(tags))
+            })
+    }
+
+    // overpass selector for route relations containing the selected ways
+    pub fn overpass_routes_selector(self) -> Option<String> {
+        let routes = one_of("route", self.routes());
+        (!self.routes().is_empty()).then(|| {
+            format!(
+                "rel(bw){}{}",
+                one_of("type", &["route"]).overpass_filter(false),
+                routes.overpass_filter(false)
+            )
+        })
+    }
+
+    // overpass selectors for all kept ways.
+    // exceptions cannot be expressed in a single selector so each one gets its own.
+    pub fn overpass_selectors(self) -> Vec<String> {
+        let plain = self
+            .excluded()
+            .iter()
+            .filter(|e| e.unless.is_none())
+            .map(|e| e.tag.overpass_filter(true))
+            .join("");
+        let exceptions = self
+            .excluded()
+            .iter()
+            .filter_map(|e| e.unless.map(|unless| (e.tag, unless)))
+            .collect::<Vec<_>>();
+        self.included()
+            .iter()
+            .flat_map(|included| {
+                let base = format!("way{}{plain}", included.overpass_filter(false));
+                let without_exceptions = exceptions
+                    .iter()
+                    .map(|(tag, _)| tag.overpass_filter(true))
+                    .join("");
+                std::iter::once(format!("{base}{without_exceptions}")).chain(exceptions.iter().map(
+                    move |(tag, unless)| {
+                        format!(
+                            "{base}{}{}",
+                            tag.overpass_filter(false),
+                            unless.overpass_filter(false)
+                        )
+                    },
+                ))
+            })
+            .collect()
+    }
+}
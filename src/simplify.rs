@@ -2,8 +2,16 @@ use super::Node;
 use std::collections::{hash_map::Entry, HashMap};
 
 pub fn simplify_path(points: &[Node], epsilon: f64) -> Vec<Node> {
+    simplify_path_indices(points, epsilon)
+        .into_iter()
+        .map(|i| points[i])
+        .collect()
+}
+
+// indices of the points kept by simplify_path
+pub fn simplify_path_indices(points: &[Node], epsilon: f64) -> Vec<usize> {
     if points.len() <= 1000 {
-        optimal_simplification2(points, epsilon)
+        optimal_simplification_indices(points, epsilon)
     } else {
         hybrid_simplification(points, epsilon)
     }
@@ -12,10 +20,20 @@ pub fn simplify_path(points: &[Node], epsilon: f64) -> Vec<Node> {
 pub fn optimal_simplification(points: &[Node], epsilon: f64) -> Vec<Node> {
     let mut cache = HashMap::new();
     simplify_prog_dyn(points, 0, points.len(), epsilon, &mut cache);
-    extract_prog_dyn_solution(points, 0, points.len(), &cache)
+    extract_prog_dyn_solution(0, points.len(), &cache)
+        .into_iter()
+        .map(|i| points[i])
+        .collect()
 }
 
 pub fn optimal_simplification2(points: &[Node], epsilon: f64) -> Vec<Node> {
+    optimal_simplification_indices(points, epsilon)
+        .into_iter()
+        .map(|i| points[i])
+        .collect()
+}
+
+fn optimal_simplification_indices(points: &[Node], epsilon: f64) -> Vec<usize> {
     let mut cache = HashMap::new();
     let mut dist_cache = HashMap::new();
     simplify_prog_dyn2(
@@ -26,12 +44,12 @@ pub fn optimal_simplification2(points: &[Node], epsilon: f64) -> Vec<Node> {
         &mut cache,
         &mut dist_cache,
     );
-    extract_prog_dyn_solution(points, 0, points.len(), &cache)
+    extract_prog_dyn_solution(0, points.len(), &cache)
 }
 
-fn hybrid_simplification(points: &[Node], epsilon: f64) -> Vec<Node> {
+fn hybrid_simplification(points: &[Node], epsilon: f64) -> Vec<usize> {
     if points.len() <= 800 {
-        optimal_simplification2(points, epsilon)
+        optimal_simplification_indices(points, epsilon)
     } else if points.first().unwrap() == points.last().unwrap() {
         let first = points.first().unwrap();
         let index_farthest = points
@@ -51,7 +69,11 @@ fn hybrid_simplification(points: &[Node], epsilon: f64) -> Vec<Node> {
         let end = &points[index_farthest..];
         let mut res = hybrid_simplification(start, epsilon);
         res.pop();
-        res.append(&mut hybrid_simplification(end, epsilon));
+        res.extend(
+            hybrid_simplification(end, epsilon)
+                .into_iter()
+                .map(|i| index_farthest + i),
+        );
         res
     } else {
         let (index_farthest, farthest_distance) = points
@@ -69,35 +91,36 @@ fn hybrid_simplification(points: &[Node], epsilon: f64) -> Vec<Node> {
             })
             .unwrap();
         if farthest_distance <= epsilon {
-            vec![
-                points.first().copied().unwrap(),
-                points.last().copied().unwrap(),
-            ]
+            vec![0, points.len() - 1]
         } else {
             let start = &points[..(index_farthest + 1)];
             let end = &points[index_farthest..];
             let mut res = hybrid_simplification(start, epsilon);
             res.pop();
-            res.append(&mut hybrid_simplification(end, epsilon));
+            res.extend(
+                hybrid_simplification(end, epsilon)
+                    .into_iter()
+                    .map(|i| index_farthest + i),
+            );
             res
         }
     }
 }
 
+// indices of the kept points
 fn extract_prog_dyn_solution(
-    points: &[Node],
     start: usize,
     end: usize,
     cache: &HashMap<(usize, usize), (Option<usize>, usize)>,
-) -> Vec<Node> {
+) -> Vec<usize> {
     if let Some(choice) = cache.get(&(start, end)).and_then(|c| c.0) {
-        let mut v1 = extract_prog_dyn_solution(points, start, choice + 1, cache);
-        let mut v2 = extract_prog_dyn_solution(points, choice, end, cache);
+        let mut v1 = extract_prog_dyn_solution(start, choice + 1, cache);
+        let mut v2 = extract_prog_dyn_solution(choice, end, cache);
         v1.pop();
         v1.append(&mut v2);
         v1
     } else {
-        vec![points[start], points[end - 1]]
+        vec![start, end - 1]
     }
 }
 
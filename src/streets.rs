@@ -2,7 +2,11 @@
 
 use std::collections::HashMap;
 
-use crate::CWayId;
+use crate::{
+    decoder::DecodeError,
+    utils::{compress, decompress},
+    CWayId,
+};
 
 use unicode_categories::UnicodeCategories;
 use unicode_normalization::UnicodeNormalization;
@@ -12,12 +16,22 @@ fn remove_accents(s: &str) -> String {
     s.nfd().filter(|&c| !c.is_mark_nonspacing()).collect()
 }
 
-pub(crate) fn encode_streets(streets: &HashMap<String, Vec<CWayId>>) -> Vec<u8> {
+// name as stored in the streets block.
+// names are separated by newlines so they cannot contain any.
+pub(crate) fn street_label(name: &str) -> String {
+    remove_accents(name).replace('\n', " ")
+}
+
+// tiles numbers are stored on tile_number_size bytes.
+pub(crate) fn encode_streets(
+    streets: &HashMap<String, Vec<CWayId>>,
+    tile_number_size: usize,
+) -> Vec<u8> {
     // sort by alphabetical order
     let mut sorted_streets = streets.iter().collect::<Vec<_>>();
     sorted_streets.sort_unstable_by(|a, b| a.0.cmp(b.0));
     // now, let's do sqrt(n) blocks of sqrt(n) streets
-    let block_size = (sorted_streets.len() as f64).sqrt().ceil() as usize;
+    let block_size = ((sorted_streets.len() as f64).sqrt().ceil() as usize).max(1);
     let mut blocks_labels = String::new();
     let mut encoded_blocks: Vec<u8> = Vec::new();
     let mut blocks_starts = Vec::new();
@@ -25,15 +39,16 @@ pub(crate) fn encode_streets(streets: &HashMap<String, Vec<CWayId>>) -> Vec<u8>
         let mut names = String::new();
         let mut ways = Vec::new();
         // let mut ways_starts = Vec::new();
-        blocks_labels.push_str(&remove_accents(streets_chunk[0].0));
+        blocks_labels.push_str(&street_label(streets_chunk[0].0));
         blocks_labels.push('\n');
         for (street_name, street_ways) in streets_chunk {
             // ways_starts.push(ways.len());
-            names.push_str(&remove_accents(street_name));
+            names.push_str(&street_label(street_name));
             names.push('\n');
             ways.extend((street_ways.len() as u16).to_le_bytes());
             for way in *street_ways {
-                ways.extend(way.tile_number.to_le_bytes());
+                ways.extend(&way.tile_number.to_le_bytes()[0..tile_number_size]);
+                ways.push(way.sub_tile);
                 ways.push(way.local_way_id);
             }
         }
@@ -44,18 +59,12 @@ pub(crate) fn encode_streets(streets: &HashMap<String, Vec<CWayId>>) -> Vec<u8>
         raw_block.extend(ways);
         raw_block.extend(names.as_bytes().iter().copied());
 
-        let mut encoded_block = vec![0u8; 2 * raw_block.len()];
-        let encoded = heatshrink::encode(
-            &raw_block,
-            &mut encoded_block,
-            &heatshrink::Config::new(8, 6).unwrap(),
-        )
-        .expect("encoding failed");
         blocks_starts.push(encoded_blocks.len());
-        encoded_blocks.extend(encoded);
+        encoded_blocks.extend(compress(&raw_block));
     }
     let mut full_encoding = Vec::new();
     let full_size = 4 // size encoding
+        + 1 // size of tiles numbers
         + 2 // number of blocks
         + 2 // size of all blocks labels
         + blocks_labels.len() // label of each block
@@ -63,6 +72,7 @@ pub(crate) fn encode_streets(streets: &HashMap<String, Vec<CWayId>>) -> Vec<u8>
         + encoded_blocks.len(); // binary encoding of each block
 
     full_encoding.extend((full_size as u32).to_le_bytes());
+    full_encoding.push(tile_number_size as u8);
     full_encoding.extend((blocks_starts.len() as u16).to_le_bytes());
     full_encoding.extend((blocks_labels.len() as u16).to_le_bytes());
     full_encoding.extend(blocks_labels.as_bytes());
@@ -73,25 +83,48 @@ pub(crate) fn encode_streets(streets: &HashMap<String, Vec<CWayId>>) -> Vec<u8>
     full_encoding
 }
 
-pub fn decode_streets(encoded: &[u8]) -> HashMap<String, Vec<CWayId>> {
+// split the first bytes off, failing if there are not enough
+fn take<'a>(bytes: &mut &'a [u8], size: usize) -> Result<&'a [u8], DecodeError> {
+    if bytes.len() < size {
+        return Err(DecodeError::Truncated);
+    }
+    let (taken, remaining) = bytes.split_at(size);
+    *bytes = remaining;
+    Ok(taken)
+}
+
+fn take_u16(bytes: &mut &[u8]) -> Result<u16, DecodeError> {
+    take(bytes, 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+pub fn decode_streets(encoded: &[u8]) -> Result<HashMap<String, Vec<CWayId>>, DecodeError> {
     let mut streets = HashMap::new();
     let real_size = encoded.len();
-    let (size_encoding, encoded) = encoded.split_at(4);
+    let mut encoded = encoded;
+    let size_encoding = take(&mut encoded, 4)?;
     let stored_size = u32::from_le_bytes([
         size_encoding[0],
         size_encoding[1],
         size_encoding[2],
         size_encoding[3],
     ]);
-    assert_eq!(stored_size as usize, real_size);
-    let (blocks_number_encoding, encoded) = encoded.split_at(2);
-    let blocks_number =
-        u16::from_le_bytes([blocks_number_encoding[0], blocks_number_encoding[1]]) as usize;
-    let (labels_size_encoding, encoded) = encoded.split_at(2);
-    let labels_size = u16::from_le_bytes([labels_size_encoding[0], labels_size_encoding[1]]);
-    let (binary_labels, encoded) = encoded.split_at(labels_size as usize);
-    let _labels = String::from_utf8(binary_labels.to_owned()).unwrap();
-    let (binary_blocks_starts, encoded) = encoded.split_at(blocks_number * 4);
+    if stored_size as usize != real_size {
+        return Err(DecodeError::BadSize(format!(
+            "streets size is {stored_size} instead of {real_size}"
+        )));
+    }
+    let tile_number_size = take(&mut encoded, 1)?[0] as usize;
+    if !(1..=4).contains(&tile_number_size) {
+        return Err(DecodeError::BadSize(format!(
+            "invalid tiles numbers size {tile_number_size}"
+        )));
+    }
+    let blocks_number = take_u16(&mut encoded)? as usize;
+    let labels_size = take_u16(&mut encoded)? as usize;
+    let binary_labels = take(&mut encoded, labels_size)?;
+    let _labels = std::str::from_utf8(binary_labels)
+        .map_err(|e| DecodeError::InvalidData(format!("invalid streets labels: {e}")))?;
+    let binary_blocks_starts = take(&mut encoded, blocks_number * 4)?;
     let blocks_starts = binary_blocks_starts
         .chunks_exact(4)
         .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]) as usize)
@@ -102,20 +135,20 @@ pub fn decode_streets(encoded: &[u8]) -> HashMap<String, Vec<CWayId>> {
             .get(block_id + 1)
             .copied()
             .unwrap_or(encoded.len());
-        let decoded_block = decode_block(&encoded[start..end]);
-        streets.extend(decoded_block);
+        let block = encoded
+            .get(start..end)
+            .ok_or_else(|| DecodeError::BadSize(format!("invalid streets block {block_id}")))?;
+        streets.extend(decode_block(block, tile_number_size)?);
     }
-    streets
+    Ok(streets)
 }
 
-fn decode_block(encoded_binary_block: &[u8]) -> Vec<(String, Vec<CWayId>)> {
-    let mut decoded_binary_block = vec![0; encoded_binary_block.len() * 2]; // duh, what a crappy API
-    let decoded = heatshrink::decode(
-        encoded_binary_block,
-        &mut decoded_binary_block,
-        &heatshrink::Config::new(8, 6).unwrap(),
-    )
-    .unwrap();
+fn decode_block(
+    encoded_binary_block: &[u8],
+    tile_number_size: usize,
+) -> Result<Vec<(String, Vec<CWayId>)>, DecodeError> {
+    let decoded_binary_block = decompress(encoded_binary_block);
+    let mut decoded = decoded_binary_block.as_slice();
 
     // let (binary_streets_number, decoded) = decoded.split_at(2);
     // let streets_number =
@@ -125,35 +158,37 @@ fn decode_block(encoded_binary_block: &[u8]) -> Vec<(String, Vec<CWayId>)> {
     //     .chunks_exact(2)
     //     .map(|c| u16::from_le_bytes([c[0], c[1]]) as usize)
     //     .collect::<Vec<_>>();
-    let (binary_ways_len, decoded) = decoded.split_at(2);
-    let ways_len = u16::from_le_bytes([binary_ways_len[0], binary_ways_len[1]]) as usize;
-    let (binary_ways, decoded) = decoded.split_at(ways_len);
+    let ways_len = take_u16(&mut decoded)? as usize;
+    let binary_ways = take(&mut decoded, ways_len)?;
 
-    let ways = decode_ways(binary_ways);
+    let ways = decode_ways(binary_ways, tile_number_size)?;
 
-    let names = String::from_utf8(decoded.to_owned()).unwrap();
-    names.split('\n').map(|n| n.to_owned()).zip(ways).collect()
+    let names = std::str::from_utf8(decoded)
+        .map_err(|e| DecodeError::InvalidData(format!("invalid street name: {e}")))?;
+    Ok(names.split('\n').map(|n| n.to_owned()).zip(ways).collect())
 }
 
-fn decode_ways(mut binary_ways: &[u8]) -> Vec<Vec<CWayId>> {
+fn decode_ways(
+    mut binary_ways: &[u8],
+    tile_number_size: usize,
+) -> Result<Vec<Vec<CWayId>>, DecodeError> {
     let mut ways = Vec::new();
     while !binary_ways.is_empty() {
-        let (binary_way_len, binary) = binary_ways.split_at(2);
-        binary_ways = binary;
-        let way_len = u16::from_le_bytes([binary_way_len[0], binary_way_len[1]]);
+        let way_len = take_u16(&mut binary_ways)?;
         let mut way = Vec::new();
         for _ in 0..way_len {
-            let (binary_tile_number, binary) = binary_ways.split_at(2);
-            binary_ways = binary;
-            let tile_number = u16::from_le_bytes([binary_tile_number[0], binary_tile_number[1]]);
-            let (local_way_id, binary) = binary_ways.split_first().unwrap();
-            binary_ways = binary;
+            let binary_tile_number = take(&mut binary_ways, tile_number_size)?;
+            let mut tile_number_bytes = [0u8; 4];
+            tile_number_bytes[..tile_number_size].copy_from_slice(binary_tile_number);
+            let tile_number = u32::from_le_bytes(tile_number_bytes);
+            let ids = take(&mut binary_ways, 2)?;
             way.push(CWayId {
                 tile_number,
-                local_way_id: *local_way_id,
+                sub_tile: ids[0],
+                local_way_id: ids[1],
             });
         }
         ways.push(way);
     }
-    ways
+    Ok(ways)
 }
@@ -65,7 +65,7 @@ impl<W: Write> Svg<W> for Node {
 
 impl<W: Write> Svg<W> for Map {
     fn write_svg(&self, writer: &mut W, color: &str) -> std::io::Result<()> {
-        let (xmin, ymin, xmax, ymax) = self.bounding_box();
+        // let (xmin, ymin, xmax, ymax) = self.bounding_box();
         // for x in grid_coordinates_between(xmin, xmax, self.side) {
         //     writeln!(
         //     writer,
@@ -85,6 +85,7 @@ impl<W: Write> Svg<W> for Map {
     }
 }
 
+#[allow(dead_code)]
 pub struct MapTiles<'a> {
     pub tiles: &'a HashSet<(usize, usize)>,
     pub map: &'a Map,
@@ -1,3 +1,6 @@
+use byteorder::ReadBytesExt;
+use std::io::{Read, Write};
+
 // loop on all coordinates c intersecting grid at min + side * alpha
 // such that start < c < end
 pub fn grid_coordinates_between(
@@ -19,3 +22,89 @@ pub fn grid_coordinates_between(
     let end_cell = (end / side).ceil() as i32;
     (real_start_cell..end_cell).map(move |alpha| alpha as f64 * side)
 }
+
+// standard crc32 (the one computed by E.CRC32 on the watch).
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+// zigzag + leb128 encoding of signed integers : small values take few bytes.
+pub fn write_varint<W: Write>(value: i64, writer: &mut W) -> std::io::Result<()> {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            return writer.write_all(&[byte]);
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+// read given number of bytes, without trusting it for the allocation
+pub fn read_bytes<R: Read>(reader: &mut R, size: usize) -> std::io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(size as u64).read_to_end(&mut bytes)?;
+    if bytes.len() == size {
+        Ok(bytes)
+    } else {
+        Err(std::io::ErrorKind::UnexpectedEof.into())
+    }
+}
+
+pub fn read_varint<R: Read>(reader: &mut R) -> std::io::Result<i64> {
+    let mut zigzag = 0u64;
+    let mut shift = 0;
+    loop {
+        if shift >= 64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "varint is too long",
+            ));
+        }
+        let byte = reader.read_u8()?;
+        zigzag |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+        shift += 7;
+    }
+}
+
+// heatshrink parameters used for all compressed data (streets and tiles)
+fn heatshrink_config() -> heatshrink::Config {
+    heatshrink::Config::new(8, 6).unwrap()
+}
+
+pub fn compress(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![0u8; 2 * bytes.len() + 16];
+    heatshrink::encode(bytes, &mut encoded, &heatshrink_config())
+        .expect("encoding failed")
+        .to_vec()
+}
+
+pub fn decompress(encoded: &[u8]) -> Vec<u8> {
+    // duh, what a crappy API : we don't know the decoded size
+    // so we grow the buffer until it fits.
+    let mut decoded = vec![0; encoded.len() * 2 + 16];
+    let config = heatshrink_config();
+    let decoded_size = loop {
+        match heatshrink::decode(encoded, &mut decoded, &config) {
+            Ok(decoded) => break decoded.len(),
+            Err(heatshrink::DecodeError::OutputFull) => {
+                let new_size = 2 * decoded.len();
+                decoded.resize(new_size, 0)
+            }
+        }
+    };
+    decoded.truncate(decoded_size);
+    decoded
+}
@@ -0,0 +1,349 @@
+// malformed .gps files must be rejected with an error, never with a panic.
+// we derive a corpus of broken files and blocks from a valid file.
+use std::collections::{HashMap, HashSet};
+
+use gps::{
+    check_gps, load_heights, load_instructions, load_interests_details, load_metadata, load_path,
+    load_tiled_interests, load_times,
+    map::{save_gps_header, DEFAULT_SIDE},
+    save_heights, save_instructions, save_interests_details, save_metadata, save_path,
+    save_tiled_interests, save_times, DecodeError, GpsFile, Instruction, Map, Node, TurnDirection,
+};
+
+// a crowded segment (to get sub-tiles) and a segment crossing several tiles
+fn path() -> (Vec<Node>, Vec<usize>) {
+    let crowded = (0..1000).map(|i| {
+        Node::new(
+            5.7667 + (i % 2) as f64 * 0.001 + (i % 37) as f64 * 6e-6,
+            45.1867 + i as f64 * 1e-6,
+        )
+    });
+    let long = (0..40).map(|i| Node::new(5.77 + i as f64 * 3e-4, 45.19 + i as f64 * 2e-4));
+    (crowded.chain(long).collect(), vec![1000])
+}
+
+fn valid_blocks() -> Vec<Vec<u8>> {
+    let (points, breaks) = path();
+    let waypoints = [
+        points[0],
+        points[300],
+        points[1020],
+        *points.last().unwrap(),
+    ]
+    .into_iter()
+    .collect::<HashSet<_>>();
+    let heights = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (*p, 200. + i as f64 * 0.3))
+        .collect::<HashMap<_, _>>();
+    let times = (0..points.len())
+        .map(|i| Some(i as f64 * 1.5))
+        .collect::<Vec<_>>();
+
+    let mut map = Map::from_path(points.clone(), &breaks, DEFAULT_SIDE);
+    let (_, tiles, max_ways) = map.stats();
+    assert!(max_ways > 255, "we want sub-tiles in the corpus");
+    let ways = (0..tiles as u32)
+        .flat_map(|tile| {
+            map.tile_ways(tile)
+                .map(|(way_id, _, _)| way_id)
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    map.streets.insert(
+        "Rue des Ecoles".to_owned(),
+        ways.iter().step_by(7).copied().collect(),
+    );
+    map.streets.insert(
+        "Avenue".to_owned(),
+        ways.iter().rev().take(20).copied().collect(),
+    );
+
+    // any valid attributes, to decode them too
+    map.attributes = (0..map.binary_ways.len() / 4)
+        .map(|i| (i % 15) as u8 | ((i % 3) << 5) as u8)
+        .collect();
+
+    let instructions = vec![Instruction {
+        point: 300,
+        direction: TurnDirection::Left,
+        angle: 90,
+        branch: 1,
+        branches: 2,
+        distance_to_next: 120,
+        street: Some("Avenue".to_owned()),
+    }];
+    let interests = points
+        .iter()
+        .step_by(50)
+        .enumerate()
+        .map(|(i, p)| (i % 3, *p))
+        .collect::<Vec<_>>();
+    let details = interests
+        .iter()
+        .enumerate()
+        .map(|(i, (interest, node))| {
+            let mut details = vec![("opening_hours".to_owned(), "24/7".to_owned())];
+            if i % 2 == 0 {
+                details.push(("name".to_owned(), format!("Fontaine {i}")));
+            }
+            (*interest, *node, details)
+        })
+        .collect::<Vec<_>>();
+    let metadata = vec![("name".to_owned(), "corpus".to_owned())];
+
+    let mut blocks = vec![Vec::new(); 11];
+    save_metadata(&metadata, &mut blocks[0]).unwrap();
+    save_tiled_interests(&interests, DEFAULT_SIDE, &mut blocks[1]).unwrap();
+    save_path(&points, &waypoints, &breaks, &mut blocks[2]).unwrap();
+    save_instructions(&instructions, &mut blocks[3]).unwrap();
+    save_heights(&points, &heights, true, &mut blocks[4]).unwrap();
+    save_heights(&points, &heights, false, &mut blocks[5]).unwrap();
+    save_times(&points, &times, &mut blocks[6]).unwrap();
+    map.save_tiles(&mut blocks[7], false).unwrap();
+    map.save_streets(&mut blocks[8]).unwrap();
+    map.save_tiles(&mut blocks[9], true).unwrap();
+    let keys = ["name".to_owned(), "opening_hours".to_owned()];
+    save_interests_details(&details, &keys, DEFAULT_SIDE, &mut blocks[10]).unwrap();
+    blocks.retain(|b| !b.is_empty());
+    blocks
+}
+
+fn file(blocks: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    save_gps_header(blocks.len(), &mut bytes).unwrap();
+    blocks.iter().for_each(|b| bytes.extend(b));
+    bytes
+}
+
+// use everything we decoded, to catch panics in accessors too
+fn exercise_map(map: &Map) {
+    let (_, tiles, _) = map.stats();
+    map.bounding_box();
+    for tile in 0..tiles as u32 {
+        map.tile_ways(tile).for_each(drop);
+    }
+    map.ways().for_each(drop);
+}
+
+fn exercise(gps: &GpsFile) {
+    gps.maps.iter().for_each(exercise_map);
+    if let Some(path) = &gps.path {
+        let segments = std::iter::once(0)
+            .chain(gps.breaks.iter().copied())
+            .chain(std::iter::once(path.len()))
+            .collect::<Vec<_>>();
+        segments
+            .windows(2)
+            .for_each(|w| assert!(path[w[0]..w[1]].len() <= path.len()));
+        for instruction in &gps.instructions {
+            assert!(instruction.point < path.len());
+        }
+        for values in [
+            gps.heights.as_ref().map(|h| h.len()),
+            gps.times.as_ref().map(|t| t.len()),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            assert_eq!(values, path.len());
+        }
+    }
+}
+
+// variations of given bytes: truncations and byte changes.
+// we try all positions in the headers and sample the rest.
+fn mutations(bytes: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    let step = (bytes.len() / 256).max(1);
+    let positions = move || (0..bytes.len()).filter(move |p| *p < 128 || p % step == 0);
+    let truncations = positions().map(|size| bytes[..size].to_vec());
+    let changes = positions().flat_map(move |position| {
+        [
+            0x00,
+            0xff,
+            0x80,
+            0x01,
+            bytes[position].wrapping_add(1),
+            bytes[position] ^ 0x10,
+        ]
+        .into_iter()
+        .map(move |value| {
+            let mut mutated = bytes.to_vec();
+            mutated[position] = value;
+            mutated
+        })
+    });
+    truncations.chain(changes)
+}
+
+// split a block into its type and content (crc removed)
+fn block_content(block: &[u8]) -> (u8, &[u8]) {
+    (block[0] & 0x7f, &block[5..block.len() - 4])
+}
+
+// same block without checksum, with its content changed
+fn unchecked_block<F: FnOnce(&mut Vec<u8>)>(block: &[u8], change: F) -> Vec<u8> {
+    let (block_type, content) = block_content(block);
+    let mut content = content.to_vec();
+    change(&mut content);
+    let mut unchecked = vec![block_type];
+    unchecked.extend((content.len() as u32).to_le_bytes());
+    unchecked.extend(content);
+    unchecked
+}
+
+// decode a block content directly, without the protection of its crc
+// (streets are decoded for the map in given tiles content)
+fn decode_content(block_type: u8, content: &[u8], tiles: &[u8], points_number: usize) {
+    let reader = &mut &content[..];
+    match block_type {
+        0 | 5 => {
+            if let Ok(map) = Map::load_tiles(reader, block_type == 5) {
+                exercise_map(&map);
+            }
+        }
+        1 => {
+            let mut map = Map::load_tiles(&mut &tiles[..], false).unwrap();
+            drop(map.load_streets(reader));
+        }
+        2 => {
+            if let Ok((path, _, breaks)) = load_path(reader) {
+                assert!(breaks.windows(2).all(|w| w[0] < w[1]));
+                assert!(breaks.iter().all(|b| *b < path.len()));
+            }
+        }
+        3 => drop(load_tiled_interests(reader)),
+        4 => drop(load_heights(points_number, reader)),
+        6 => drop(load_metadata(reader)),
+        7 => drop(load_times(points_number, reader)),
+        8 => drop(load_instructions(reader)),
+        9 => drop(load_interests_details(reader)),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn valid_file_decodes() {
+    let blocks = valid_blocks();
+    let gps = GpsFile::read(&mut file(&blocks).as_slice()).unwrap();
+    assert_eq!(gps.maps.len(), 2);
+    assert_eq!(gps.maps[0].streets.len(), 2);
+    assert_eq!(gps.breaks, vec![1000]);
+    // details follow the order of the interests block
+    assert_eq!(gps.interests_details.len(), gps.interests.len());
+    assert!(
+        gps.interests_details
+            .iter()
+            .all(|details| details.last().unwrap()
+                == &("opening_hours".to_owned(), "24/7".to_owned()))
+    );
+    assert_eq!(
+        gps.interests_details
+            .iter()
+            .filter(|details| details.len() == 2)
+            .count(),
+        gps.interests.len().div_ceil(2)
+    );
+    exercise(&gps);
+}
+
+#[test]
+fn valid_file_passes_checks() {
+    let violations = check_gps(&mut file(&valid_blocks()).as_slice());
+    assert!(violations.is_empty(), "{violations:?}");
+}
+
+#[test]
+fn checks_find_broken_interests_and_breaks() {
+    let mut blocks = valid_blocks();
+    // last interest is cut and last break is not finished
+    blocks[1] = unchecked_block(&blocks[1], |content| content.truncate(content.len() - 1));
+    blocks[2] = unchecked_block(&blocks[2], |content| content.truncate(content.len() - 1));
+    let violations = check_gps(&mut file(&blocks).as_slice());
+    let messages = |block| {
+        violations
+            .iter()
+            .filter(|v| v.block == block)
+            .map(|v| v.message.as_str())
+            .collect::<Vec<_>>()
+    };
+    assert!(messages(1)[0].starts_with("interests end at"));
+    assert!(messages(2)[0].starts_with("invalid breaks"));
+    assert_eq!(violations.len(), 2, "{violations:?}");
+}
+
+#[test]
+fn checks_find_coordinates_outside_their_tiles() {
+    // move the start coordinates one tile away from the first tile
+    fn shift(content: &mut [u8], start: usize, side: usize) {
+        let side = f64::from_le_bytes(content[side..side + 8].try_into().unwrap());
+        let x = f64::from_le_bytes(content[start..start + 8].try_into().unwrap());
+        content[start..start + 8].copy_from_slice(&(x + side).to_le_bytes());
+    }
+    let mut blocks = valid_blocks();
+    // interests: i32 x2, u32 x2 then xmin, ymin, side
+    blocks[1] = unchecked_block(&blocks[1], |content| shift(content, 16, 32));
+    // tiles: color, i32 x2, u32 x2 then start coordinates and side
+    blocks[7] = unchecked_block(&blocks[7], |content| shift(content, 19, 35));
+    let violations = check_gps(&mut file(&blocks).as_slice());
+    assert!(!violations.is_empty());
+    assert!(
+        violations
+            .iter()
+            .all(|v| [1, 7].contains(&v.block) && v.message.contains("outside the tile")),
+        "{violations:?}"
+    );
+    assert!(violations.iter().any(|v| v.block == 1));
+    assert!(violations.iter().any(|v| v.block == 7));
+}
+
+#[test]
+fn corrupted_files_do_not_panic() {
+    let bytes = file(&valid_blocks());
+    for mutated in mutations(&bytes) {
+        if let Ok(gps) = GpsFile::read(&mut mutated.as_slice()) {
+            exercise(&gps);
+        }
+    }
+}
+
+#[test]
+fn corrupted_blocks_do_not_panic() {
+    let blocks = valid_blocks();
+    let tiles = block_content(&blocks[7]).1;
+    let points_number = path().0.len();
+    for block in &blocks {
+        let (block_type, content) = block_content(block);
+        for mutated in mutations(content) {
+            decode_content(block_type, &mutated, tiles, points_number);
+        }
+    }
+}
+
+#[test]
+fn random_bytes_do_not_panic() {
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    };
+    let blocks = valid_blocks();
+    let tiles = block_content(&blocks[7]).1;
+    for _ in 0..2000 {
+        let size = (random() % 300) as usize;
+        let bytes = (0..size).map(|_| random() as u8).collect::<Vec<_>>();
+        for block_type in [0, 1, 2, 3, 4, 5, 6, 7, 8, 9] {
+            decode_content(block_type, &bytes, tiles, 640);
+        }
+        let mut file = gps::GPS_MAGIC.to_vec();
+        file.extend(gps::GPS_FORMAT_VERSION.to_le_bytes());
+        file.extend(&bytes);
+        assert!(!matches!(
+            GpsFile::read(&mut file.as_slice()),
+            Err(DecodeError::NotGps)
+        ));
+    }
+}
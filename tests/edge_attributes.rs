@@ -0,0 +1,147 @@
+// road class, surface and oneway of each edge go from osm tags to the tiles.
+use gps::{
+    map::DEFAULT_SIDE, maps_and_interests_from_string, parse_osm_xml, EdgeAttributes, Map, Node,
+    Oneway, Profile, RoadClass,
+};
+
+// a gravel street we can only take westward, crossing a paved cycleway
+const OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.1867" lon="5.7600"/>
+  <node id="2" lat="45.1867" lon="5.7650"/>
+  <node id="3" lat="45.1867" lon="5.7700"/>
+  <node id="4" lat="45.1820" lon="5.7650"/>
+  <node id="5" lat="45.1900" lon="5.7650"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="residential"/>
+    <tag k="surface" v="gravel"/>
+    <tag k="oneway" v="-1"/>
+  </way>
+  <way id="11">
+    <nd ref="4"/>
+    <nd ref="2"/>
+    <nd ref="5"/>
+    <tag k="highway" v="cycleway"/>
+  </way>
+</osm>
+"#;
+
+#[test]
+fn attributes_come_from_tags() {
+    let (.., attributes) = parse_osm_xml(|| Ok(OSM.as_bytes()), &[], Profile::default()).unwrap();
+    assert_eq!(
+        attributes[&10],
+        EdgeAttributes {
+            class: RoadClass::Residential,
+            unpaved: true,
+            oneway: Oneway::Backward,
+        }
+    );
+    assert_eq!(
+        attributes[&11],
+        EdgeAttributes {
+            class: RoadClass::Cycleway,
+            ..Default::default()
+        }
+    );
+    // stored on one byte
+    for attributes in attributes.values() {
+        assert_eq!(
+            EdgeAttributes::try_from(u8::from(*attributes)).unwrap(),
+            *attributes
+        );
+    }
+    assert!(EdgeAttributes::try_from(0xff).is_err());
+}
+
+#[test]
+fn attributes_follow_edges_into_tiles() {
+    let (maps, _) =
+        maps_and_interests_from_string(OSM, &[], Profile::default(), true, DEFAULT_SIDE).unwrap();
+    let map = &maps[0];
+    let check = |map: &Map| {
+        let edges = (0..map.tiles_sizes_prefix.len() as u32)
+            .flat_map(|tile| map.tile_ways(tile))
+            .collect::<Vec<_>>();
+        assert!(edges
+            .iter()
+            .any(|(_, _, a)| a.unwrap().class == RoadClass::Cycleway));
+        for (_, [n1, n2], attributes) in edges {
+            let attributes = attributes.unwrap();
+            if attributes.class != RoadClass::Residential {
+                assert_eq!(attributes.oneway, Oneway::No);
+                continue;
+            }
+            assert!(attributes.unpaved);
+            // oneway is relative to the stored edge : we always go west
+            match attributes.oneway {
+                Oneway::Forward => assert!(n2.x < n1.x),
+                Oneway::Backward => assert!(n1.x < n2.x),
+                Oneway::No => panic!("street is oneway"),
+            }
+        }
+    };
+    check(map);
+
+    let mut binary = Vec::new();
+    map.save_tiles(&mut binary, true).unwrap();
+    let loaded = Map::load_tiles(&mut &binary[5..], true).unwrap();
+    assert_eq!(loaded.attributes, map.attributes);
+    check(&loaded);
+
+    // paths have no attributes
+    let path = Map::from_path(
+        vec![Node::new(5.76, 45.18), Node::new(5.77, 45.18)],
+        &[],
+        DEFAULT_SIDE,
+    );
+    assert!(path.attributes.is_empty());
+    assert!(path.ways().count() > 0);
+    assert!((0..path.tiles_sizes_prefix.len() as u32)
+        .flat_map(|tile| path.tile_ways(tile))
+        .all(|(_, _, attributes)| attributes.is_none()));
+}
+
+// a cycleway on top of a residential street
+const OVERLAP: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.1867" lon="5.7600"/>
+  <node id="2" lat="45.1867" lon="5.7700"/>
+  <node id="3" lat="45.1867" lon="5.7620"/>
+  <node id="4" lat="45.1867" lon="5.7680"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <tag k="highway" v="residential"/>
+  </way>
+  <way id="11">
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <tag k="highway" v="cycleway"/>
+  </way>
+</osm>
+"#;
+
+#[test]
+fn attributes_do_not_change_maps_unless_asked_for() {
+    let tiles = |osm: &str, edge_attributes: bool| {
+        let (maps, _) = maps_and_interests_from_string(
+            osm,
+            &[],
+            Profile::default(),
+            edge_attributes,
+            DEFAULT_SIDE,
+        )
+        .unwrap();
+        let mut binary = Vec::new();
+        maps[0].save_tiles(&mut binary, false).unwrap();
+        binary
+    };
+    // without attributes both ways are the same residential street
+    let same_tags = OVERLAP.replace("cycleway", "residential");
+    assert_eq!(tiles(OVERLAP, false), tiles(&same_tags, false));
+    assert_ne!(tiles(OVERLAP, true), tiles(&same_tags, true));
+}
@@ -0,0 +1,177 @@
+// map requests must work without internet, against any osm source.
+use std::{error::Error, time::Duration};
+
+use gps::{request_maps_from, Node, OsmFile, Overpass, Profile, DEFAULT_USER_AGENT};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+const OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.1867" lon="5.7667"/>
+  <node id="2" lat="45.1870" lon="5.7672"/>
+  <node id="3" lat="45.1874" lon="5.7680">
+    <tag k="amenity" v="drinking_water"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="residential"/>
+    <tag k="name" v="Rue des Ecoles"/>
+  </way>
+</osm>
+"#;
+
+fn polygon() -> Vec<Node> {
+    vec![
+        Node::new(5.76, 45.18),
+        Node::new(5.77, 45.18),
+        Node::new(5.77, 45.19),
+        Node::new(5.76, 45.19),
+    ]
+}
+
+fn key_values() -> Vec<(String, String)> {
+    vec![("amenity".to_owned(), "drinking_water".to_owned())]
+}
+
+#[tokio::test]
+async fn closure_source() {
+    let source = |query: &str| -> Result<String, Box<dyn Error>> {
+        assert!(query.contains("poly:\"45.18 5.76 45.18 5.77"));
+        Ok(OSM.to_owned())
+    };
+    let no_map: Option<&str> = None;
+    let (maps, interests) = request_maps_from(
+        &[polygon()],
+        &key_values(),
+        no_map,
+        Profile::default(),
+        false,
+        &source,
+    )
+    .await
+    .unwrap();
+    assert_eq!(maps.len(), 1);
+    assert_eq!(maps[0].streets.len(), 1);
+    assert_eq!(interests.len(), 1);
+}
+
+#[tokio::test]
+async fn file_source() {
+    let path = std::env::temp_dir().join(format!("gps_osm_source_{}.osm", std::process::id()));
+    std::fs::write(&path, OSM).unwrap();
+    let no_map: Option<&str> = None;
+    let answer = request_maps_from(
+        &[polygon()],
+        &key_values(),
+        no_map,
+        Profile::default(),
+        false,
+        &OsmFile(path.clone()),
+    )
+    .await;
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(answer.unwrap().0.len(), 1);
+
+    let missing = OsmFile(path);
+    assert!(request_maps_from(
+        &[polygon()],
+        &key_values(),
+        no_map,
+        Profile::default(),
+        false,
+        &missing
+    )
+    .await
+    .is_err());
+}
+
+// a one shot http server answering with given status and body.
+// returns its address and the received request.
+async fn stub_server(
+    status: &'static str,
+    body: &'static str,
+) -> (String, tokio::task::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 4096];
+        // read headers and body (content-length is enough for reqwest posts)
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some(end) = text.find("\r\n\r\n") {
+                let length = text[..end]
+                    .lines()
+                    .find_map(|l| {
+                        l.to_lowercase()
+                            .strip_prefix("content-length:")
+                            .map(|v| v.trim().parse::<usize>().unwrap())
+                    })
+                    .unwrap_or(0);
+                if request.len() >= end + 4 + length || read == 0 {
+                    break;
+                }
+            }
+        }
+        let response = format!(
+            "HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8(request).unwrap()
+    });
+    (format!("http://{address}/api/interpreter"), server)
+}
+
+#[tokio::test]
+async fn overpass_endpoint_and_user_agent() {
+    let (endpoint, server) = stub_server("200 OK", OSM).await;
+    let overpass = Overpass {
+        endpoint,
+        timeout: Some(Duration::from_secs(10)),
+        ..Default::default()
+    };
+    let no_map: Option<&str> = None;
+    let (maps, _) = request_maps_from(
+        &[polygon()],
+        &key_values(),
+        no_map,
+        Profile::default(),
+        false,
+        &overpass,
+    )
+    .await
+    .unwrap();
+    assert_eq!(maps.len(), 1);
+    let request = server.await.unwrap().to_lowercase();
+    assert!(request.starts_with("post /api/interpreter"));
+    assert!(request.contains(&format!("user-agent: {DEFAULT_USER_AGENT}")));
+}
+
+#[tokio::test]
+async fn overpass_errors_are_reported() {
+    let (endpoint, server) = stub_server("429 Too Many Requests", "rate limited").await;
+    let overpass = Overpass {
+        endpoint,
+        ..Default::default()
+    };
+    let no_map: Option<&str> = None;
+    assert!(request_maps_from(
+        &[polygon()],
+        &key_values(),
+        no_map,
+        Profile::default(),
+        false,
+        &overpass
+    )
+    .await
+    .is_err());
+    server.await.unwrap();
+}
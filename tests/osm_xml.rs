@@ -0,0 +1,278 @@
+// osm xml is streamed from any reader and broken answers are errors, not panics.
+use std::io::Read;
+
+use gps::{
+    load_maps_and_interests, map::DEFAULT_SIDE, maps_and_interests_from_string, parse_osm_xml,
+    Profile, Route,
+};
+
+const OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.1867" lon="5.7667"/>
+  <node id="2" lat="45.1870" lon="5.7672"/>
+  <node id="3" lat="45.1874" lon="5.7680">
+    <tag k="amenity" v="drinking_water"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="residential"/>
+    <tag k="name" v="Rue des Écoles"/>
+  </way>
+  <way id="11">
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <tag k="highway" v="motorway"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="10" role=""/>
+    <member type="way" ref="11" role=""/>
+    <member type="way" ref="12" role=""/>
+    <member type="node" ref="1" role=""/>
+    <tag k="type" v="route"/>
+    <tag k="route" v="bicycle"/>
+    <tag k="network" v="rcn"/>
+    <tag k="ref" v="V63"/>
+    <tag k="name" v="Vélo Route 63"/>
+  </relation>
+  <relation id="21">
+    <member type="way" ref="10" role=""/>
+    <tag k="type" v="route"/>
+    <tag k="route" v="hiking"/>
+    <tag k="ref" v="GR5"/>
+  </relation>
+</osm>
+"#;
+
+// hand out bytes a few at a time, like a slow network answer
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = buf.len().min(self.0.len()).min(3);
+        buf[..size].copy_from_slice(&self.0[..size]);
+        self.0 = &self.0[size..];
+        Ok(size)
+    }
+}
+
+fn key_values() -> Vec<(String, String)> {
+    vec![("amenity".to_owned(), "drinking_water".to_owned())]
+}
+
+#[test]
+fn streamed_xml_is_parsed() {
+    let (nodes, ways, streets, _, interests, routes, _) = parse_osm_xml(
+        || Ok(Trickle(OSM.as_bytes())),
+        &key_values(),
+        Profile::default(),
+    )
+    .unwrap();
+    assert_eq!(nodes.len(), 3);
+    assert_eq!(ways.len(), 1);
+    assert_eq!(ways[&10], [1, 2, 3]);
+    assert_eq!(streets["Rue des Écoles"], [10]);
+    assert_eq!(interests.len(), 1);
+    assert_eq!(
+        interests[0].2,
+        [("amenity".to_owned(), "drinking_water".to_owned())]
+    );
+    // hiking routes are not for bicycles, and we only keep members we have
+    assert_eq!(
+        routes,
+        [Route {
+            kind: "bicycle".to_owned(),
+            network: Some("rcn".to_owned()),
+            reference: Some("V63".to_owned()),
+            name: Some("Vélo Route 63".to_owned()),
+            ways: vec![10],
+        }]
+    );
+}
+
+#[test]
+fn routes_get_their_own_layer() {
+    let (maps, _) =
+        maps_and_interests_from_string(OSM, &key_values(), Profile::Hiking, false, DEFAULT_SIDE)
+            .unwrap();
+    assert_eq!(maps.len(), 2);
+    assert_eq!(maps[1].color, [254, 0, 0]);
+    assert!(maps[0].streets.contains_key("GR5"));
+    assert_eq!(maps[1].streets.keys().collect::<Vec<_>>(), ["GR5"]);
+}
+
+#[test]
+fn broken_xml_is_an_error() {
+    let truncated = &OSM.as_bytes()[..OSM.len() / 2];
+    assert!(parse_osm_xml(|| Ok(truncated), &key_values(), Profile::default()).is_err());
+
+    let mut invalid = OSM.as_bytes().to_vec();
+    let accent = OSM.find('É').unwrap();
+    invalid[accent + 1] = 0xff;
+    assert!(parse_osm_xml(|| Ok(invalid.as_slice()), &key_values(), Profile::default()).is_err());
+
+    let path = std::env::temp_dir().join(format!("gps_osm_xml_{}.map", std::process::id()));
+    std::fs::write(&path, &invalid).unwrap();
+    let loaded = load_maps_and_interests(&path, &key_values(), Profile::default(), false);
+    std::fs::remove_file(&path).unwrap();
+    assert!(loaded.is_err());
+}
+
+// a square with three streets reaching three of its corners
+const SQUARE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.1900" lon="5.7200"/>
+  <node id="2" lat="45.1900" lon="5.7210"/>
+  <node id="3" lat="45.1910" lon="5.7210"/>
+  <node id="4" lat="45.1910" lon="5.7200"/>
+  <node id="5" lat="45.1890" lon="5.7200"/>
+  <node id="6" lat="45.1890" lon="5.7220"/>
+  <node id="7" lat="45.1920" lon="5.7210"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <nd ref="1"/>
+    <tag k="highway" v="pedestrian"/>
+    <tag k="area" v="yes"/>
+    <tag k="name" v="Place Grenette"/>
+  </way>
+  <way id="11">
+    <nd ref="5"/>
+    <nd ref="1"/>
+    <tag k="highway" v="residential"/>
+  </way>
+  <way id="12">
+    <nd ref="6"/>
+    <nd ref="2"/>
+    <tag k="highway" v="residential"/>
+  </way>
+  <way id="13">
+    <nd ref="7"/>
+    <nd ref="3"/>
+    <tag k="highway" v="residential"/>
+  </way>
+</osm>
+"#;
+
+#[test]
+fn areas_are_crossed_between_entrances() {
+    let (_, ways, streets, ..) =
+        parse_osm_xml(|| Ok(SQUARE.as_bytes()), &[], Profile::default()).unwrap();
+    let mut connectors = ways
+        .iter()
+        .filter(|(id, _)| !(10..=13).contains(*id))
+        .map(|(_, way)| {
+            let mut way = way.clone();
+            way.sort();
+            way
+        })
+        .collect::<Vec<_>>();
+    connectors.sort();
+    assert_eq!(connectors, [[1, 2], [1, 3], [2, 3]]);
+    // the outline stays and connectors take the square's name
+    assert_eq!(ways[&10], [1, 2, 3, 4, 1]);
+    assert_eq!(streets["Place Grenette"].len(), 4);
+
+    let (maps, _) =
+        maps_and_interests_from_string(SQUARE, &[], Profile::default(), false, DEFAULT_SIDE)
+            .unwrap();
+    assert_eq!(maps.len(), 1);
+}
+
+// a toilets building, and a multipolygon shelter whose outer ring is made of two ways
+// (one of them reversed)
+const SHAPES: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.0000" lon="5.0000"/>
+  <node id="2" lat="45.0000" lon="5.0020"/>
+  <node id="3" lat="45.0010" lon="5.0020"/>
+  <node id="4" lat="45.0010" lon="5.0000"/>
+  <node id="5" lat="45.1000" lon="5.1000"/>
+  <node id="6" lat="45.1000" lon="5.1010"/>
+  <node id="7" lat="45.1020" lon="5.1010"/>
+  <node id="8" lat="45.1010" lon="5.1000"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <nd ref="1"/>
+    <tag k="building" v="yes"/>
+    <tag k="amenity" v="toilets"/>
+  </way>
+  <way id="11">
+    <nd ref="5"/>
+    <nd ref="6"/>
+    <nd ref="7"/>
+  </way>
+  <way id="12">
+    <nd ref="5"/>
+    <nd ref="8"/>
+    <nd ref="7"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="11" role="outer"/>
+    <member type="way" ref="12" role="outer"/>
+    <tag k="type" v="multipolygon"/>
+    <tag k="amenity" v="shelter"/>
+    <tag k="name" v="Refuge des Écrins"/>
+  </relation>
+</osm>
+"#;
+
+#[test]
+fn interests_can_be_ways_or_relations() {
+    let key_values = [
+        ("amenity".to_owned(), "shelter".to_owned()),
+        ("amenity".to_owned(), "toilets".to_owned()),
+    ];
+    let (_, ways, _, _, interests, ..) =
+        parse_osm_xml(|| Ok(SHAPES.as_bytes()), &key_values, Profile::default()).unwrap();
+    assert!(ways.is_empty());
+    assert_eq!(interests.len(), 2);
+    // the building's centroid
+    let (interest, point, _) = interests[0].clone();
+    assert_eq!(interest, 2);
+    assert!((point.x - 5.001).abs() < 1e-9 && (point.y - 45.0005).abs() < 1e-9);
+    // the centroid of the relation's outer ring
+    let (interest, point, details) = interests[1].clone();
+    assert_eq!(interest, 1);
+    assert!(
+        (point.x - (5.1 + 0.005 / 9.)).abs() < 1e-9 && (point.y - (45.1 + 0.007 / 9.)).abs() < 1e-9
+    );
+    // all tags are kept for the details block
+    assert!(details.contains(&("name".to_owned(), "Refuge des Écrins".to_owned())));
+}
+
+#[test]
+fn answers_are_read_again_only_for_missing_interests_ways() {
+    let shelters = [("amenity".to_owned(), "shelter".to_owned())];
+    let mut opened = 0;
+    let (_, _, _, _, interests, ..) = parse_osm_xml(
+        || {
+            opened += 1;
+            Ok(SHAPES.as_bytes())
+        },
+        &shelters,
+        Profile::default(),
+    )
+    .unwrap();
+    // the shelter's ways are not on the map nor interests themselves
+    assert_eq!(opened, 2);
+    assert_eq!(interests.len(), 1);
+
+    let mut opened = 0;
+    parse_osm_xml(
+        || {
+            opened += 1;
+            Ok(OSM.as_bytes())
+        },
+        &key_values(),
+        Profile::default(),
+    )
+    .unwrap();
+    assert_eq!(opened, 1);
+}
@@ -0,0 +1,203 @@
+// extracts are clipped to the polygon and filtered like overpass answers.
+// osmio cannot write pbf files so we go through its xml reader, which yields the same objects.
+#![cfg(feature = "pbf")]
+use gps::{
+    map::DEFAULT_SIDE, maps_and_interests_from_osm, parse_osm_objects, parse_osm_pbf, Node, Profile,
+};
+use osmio::{xml::XMLReader, OSMReader};
+
+const OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.1867" lon="5.7667"/>
+  <node id="2" lat="45.1870" lon="5.7672"/>
+  <node id="3" lat="45.1874" lon="5.7680">
+    <tag k="amenity" v="drinking_water"/>
+  </node>
+  <node id="4" lat="45.1950" lon="5.7750"/>
+  <node id="5" lat="45.1960" lon="5.7760">
+    <tag k="amenity" v="drinking_water"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <tag k="highway" v="residential"/>
+    <tag k="name" v="Rue des Ecoles"/>
+  </way>
+  <way id="11">
+    <nd ref="2"/>
+    <nd ref="4"/>
+    <tag k="highway" v="cycleway"/>
+  </way>
+  <way id="12">
+    <nd ref="4"/>
+    <nd ref="5"/>
+    <tag k="highway" v="residential"/>
+  </way>
+  <way id="13">
+    <nd ref="1"/>
+    <nd ref="3"/>
+    <tag k="highway" v="motorway_link"/>
+  </way>
+  <way id="14">
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="footway"/>
+  </way>
+  <way id="15">
+    <nd ref="1"/>
+    <nd ref="3"/>
+    <tag k="highway" v="footway"/>
+    <tag k="bicycle" v="yes"/>
+  </way>
+  <way id="16">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <nd ref="1"/>
+    <tag k="amenity" v="drinking_water"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="10" role=""/>
+    <member type="way" ref="12" role=""/>
+    <tag k="type" v="route"/>
+    <tag k="route" v="bicycle"/>
+    <tag k="ref" v="V63"/>
+  </relation>
+</osm>
+"#;
+
+fn polygon() -> Vec<Node> {
+    vec![
+        Node::new(5.76, 45.18),
+        Node::new(5.77, 45.18),
+        Node::new(5.77, 45.19),
+        Node::new(5.76, 45.19),
+    ]
+}
+
+#[test]
+fn extract_is_clipped_and_filtered() {
+    let key_values = [("amenity".to_owned(), "drinking_water".to_owned())];
+    let osm = parse_osm_objects(
+        || Ok(XMLReader::new(OSM.as_bytes())),
+        &[polygon()],
+        &key_values,
+        Profile::default(),
+    )
+    .unwrap();
+    let (nodes, ways, streets, _, interests, routes, _) = &osm;
+
+    let mut kept = ways.keys().copied().collect::<Vec<_>>();
+    kept.sort();
+    // 12 is outside, 13 is a motorway and 14 is a footway without bicycles
+    assert_eq!(kept, [10, 11, 15]);
+    // node 4 is outside but needed by way 11
+    let mut kept_nodes = nodes.keys().copied().collect::<Vec<_>>();
+    kept_nodes.sort();
+    assert_eq!(kept_nodes, [1, 2, 3, 4]);
+    assert_eq!(streets["Rue des Ecoles"], [10]);
+    assert_eq!(interests.len(), 2);
+    assert_eq!(interests[0].0, 1);
+    assert_eq!(interests[0].1, Node::new(5.7680, 45.1874));
+    // way 16 is not on the map but its centroid is an interest
+    let (interest, point, _) = interests[1].clone();
+    assert_eq!(interest, 1);
+    assert!((point.x - (5.7667 + 5.7672 + 5.7680) / 3.).abs() < 1e-9);
+    assert!((point.y - (45.1867 + 45.1870 + 45.1874) / 3.).abs() < 1e-9);
+    // way 12 is not on the map
+    assert_eq!(routes.len(), 1);
+    assert_eq!(routes[0].ways, [10]);
+
+    let (maps, _) = maps_and_interests_from_osm(osm, Profile::default(), false, DEFAULT_SIDE);
+    assert_eq!(maps.len(), 2);
+}
+
+// a square shelter whose outer ring is split in a way crossing the polygon's border
+// and a way completely outside of it
+const SHELTER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.185" lon="5.765"/>
+  <node id="2" lat="45.185" lon="5.775"/>
+  <node id="3" lat="45.195" lon="5.775"/>
+  <node id="4" lat="45.195" lon="5.765"/>
+  <way id="31">
+    <nd ref="4"/>
+    <nd ref="1"/>
+    <nd ref="2"/>
+  </way>
+  <way id="32">
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <nd ref="4"/>
+  </way>
+  <relation id="40">
+    <member type="way" ref="31" role="outer"/>
+    <member type="way" ref="32" role="outer"/>
+    <tag k="type" v="multipolygon"/>
+    <tag k="amenity" v="shelter"/>
+  </relation>
+</osm>
+"#;
+
+#[test]
+fn interests_relations_get_their_ways_outside_the_polygon() {
+    let key_values = [("amenity".to_owned(), "shelter".to_owned())];
+    let (_, _, _, _, interests, _, _) = parse_osm_objects(
+        || Ok(XMLReader::new(SHELTER.as_bytes())),
+        &[polygon()],
+        &key_values,
+        Profile::default(),
+    )
+    .unwrap();
+    assert_eq!(interests.len(), 1);
+    let (_, point, _) = &interests[0];
+    assert!((point.x - 5.770).abs() < 1e-9);
+    assert!((point.y - 45.190).abs() < 1e-9);
+}
+
+// a long cycleway crossing the polygon from west to east, and a residential
+// street passing by
+const CROSSING: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.185" lon="5.750"/>
+  <node id="2" lat="45.185" lon="5.780"/>
+  <node id="3" lat="45.175" lon="5.758"/>
+  <node id="4" lat="45.185" lon="5.752"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <tag k="highway" v="cycleway"/>
+  </way>
+  <way id="11">
+    <nd ref="3"/>
+    <nd ref="4"/>
+    <tag k="highway" v="residential"/>
+  </way>
+</osm>
+"#;
+
+#[test]
+fn ways_crossing_the_polygon_without_nodes_inside_are_kept() {
+    let (nodes, ways, _, _, _, _, _) = parse_osm_objects(
+        || Ok(XMLReader::new(CROSSING.as_bytes())),
+        &[polygon()],
+        &[],
+        Profile::default(),
+    )
+    .unwrap();
+    assert_eq!(ways.keys().copied().collect::<Vec<_>>(), [10]);
+    assert_eq!(nodes[&1], Node::new(5.750, 45.185));
+    assert_eq!(nodes[&2], Node::new(5.780, 45.185));
+    assert_eq!(nodes.len(), 2);
+}
+
+#[test]
+fn missing_extract_is_an_error() {
+    assert!(parse_osm_pbf(
+        "/nonexistent/region.osm.pbf",
+        &[polygon()],
+        &[],
+        Profile::default()
+    )
+    .is_err());
+}
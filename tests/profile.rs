@@ -0,0 +1,160 @@
+// profiles generate the overpass query and filter parsed ways:
+// both must select exactly the same ways.
+use std::collections::HashMap;
+
+use gps::{overpass_query, Node, Profile};
+
+const PROFILES: [Profile; 5] = [
+    Profile::RoadCycling,
+    Profile::Gravel,
+    Profile::Hiking,
+    Profile::Running,
+    Profile::Ski,
+];
+
+// tags of some ways, as key=value pairs
+const WAYS: &[&[(&str, &str)]] = &[
+    &[("highway", "residential"), ("name", "Rue des Ecoles")],
+    &[("highway", "motorway")],
+    &[("highway", "motorway_link")],
+    &[("highway", "trunk_link")],
+    &[("highway", "raceway")],
+    &[("highway", "steps")],
+    &[("highway", "footway")],
+    &[("highway", "footway"), ("bicycle", "yes")],
+    &[("highway", "footway"), ("footway", "crossing")],
+    &[
+        ("highway", "footway"),
+        ("footway", "crossing"),
+        ("bicycle", "yes"),
+    ],
+    &[("highway", "pedestrian"), ("area", "yes")],
+    &[("highway", "track"), ("surface", "gravel")],
+    &[("highway", "tertiary"), ("surface", "gravel")],
+    &[("highway", "path"), ("sac_scale", "alpine_hiking")],
+    &[("highway", "path"), ("sac_scale", "hiking")],
+    &[("highway", "service"), ("service", "parking_aisle")],
+    &[("piste:type", "downhill"), ("piste:difficulty", "easy")],
+    &[("piste:type", "nordic")],
+    &[("aerialway", "chair_lift")],
+    &[("building", "yes")],
+];
+
+fn tags(way: &[(&'static str, &'static str)]) -> HashMap<&'static str, &'static str> {
+    way.iter().copied().collect()
+}
+
+// evaluate one overpass filter like ["key"!~"^(a|b)$"] on given tags
+fn filter_matches(filter: &str, tags: &HashMap<&str, &str>) -> bool {
+    if let Some(key) = filter.strip_prefix('!') {
+        return !tags.contains_key(key.trim_matches('"'));
+    }
+    let operators = ["!~", "!=", "~", "="];
+    let Some((key, operator, value)) = operators.iter().find_map(|operator| {
+        filter
+            .split_once(&format!("\"{operator}\""))
+            .map(|(key, value)| (key, *operator, value))
+    }) else {
+        return tags.contains_key(filter.trim_matches('"'));
+    };
+    let (key, value) = (key.trim_matches('"'), value.trim_matches('"'));
+    let values = value
+        .strip_prefix("^(")
+        .and_then(|v| v.strip_suffix(")$"))
+        .map(|v| v.split('|').collect::<Vec<_>>())
+        .unwrap_or_else(|| vec![value]);
+    let matched = tags.get(key).is_some_and(|v| values.contains(v));
+    match operator {
+        "=" | "~" => matched,
+        _ => !matched,
+    }
+}
+
+fn selector_matches(selector: &str, tags: &HashMap<&str, &str>) -> bool {
+    let filters = selector
+        .strip_prefix("way[")
+        .unwrap()
+        .strip_suffix(']')
+        .unwrap();
+    filters
+        .split("][")
+        .all(|filter| filter_matches(filter, tags))
+}
+
+#[test]
+fn query_and_filter_agree() {
+    for profile in PROFILES {
+        let selectors = profile.overpass_selectors();
+        for way in WAYS {
+            let tags = tags(way);
+            assert_eq!(
+                profile.keeps(&tags),
+                selectors.iter().any(|s| selector_matches(s, &tags)),
+                "{profile:?} on {way:?} with {selectors:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn profiles_keep_their_ways() {
+    let kept = |profile: Profile| {
+        WAYS.iter()
+            .enumerate()
+            .filter(|(_, way)| profile.keeps(&tags(way)))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(kept(Profile::Gravel), [0, 7, 10, 11, 12, 13, 14, 15]);
+    assert_eq!(kept(Profile::RoadCycling), [0, 7, 10]);
+    assert_eq!(kept(Profile::Hiking), [0, 5, 6, 7, 10, 11, 12, 13, 14, 15]);
+    assert_eq!(kept(Profile::Running), [0, 5, 6, 7, 10, 11, 12, 14, 15]);
+    assert_eq!(kept(Profile::Ski), [16, 18]);
+}
+
+#[test]
+fn query_selects_ways_in_polygon() {
+    let polygons = [vec![
+        Node::new(5.76, 45.18),
+        Node::new(5.77, 45.18),
+        Node::new(5.77, 45.19),
+    ]];
+    for profile in PROFILES {
+        let query = overpass_query(&polygons, profile, &[]);
+        let statements = query
+            .matches("(poly:\"45.18 5.76 45.18 5.77 45.19 5.77\");")
+            .count();
+        assert_eq!(statements, profile.overpass_selectors().len() + 1);
+        assert!(query.ends_with("out body;"));
+    }
+    let query = overpass_query(&polygons, Profile::Hiking, &[]);
+    assert!(
+        query.ends_with("rel(bw)[\"type\"=\"route\"][\"route\"~\"^(hiking|foot)$\"];\nout body;")
+    );
+    assert!(!overpass_query(&polygons, Profile::Ski, &[]).contains("rel"));
+    let query = overpass_query(&polygons, Profile::Gravel, &[]);
+    assert!(query.contains("motorway_link"));
+    assert!(!query.contains("hightway"));
+    // each statement is repeated for each polygon
+    let two_polygons = [polygons[0].clone(), vec![Node::new(6., 46.); 3]];
+    let query = overpass_query(&two_polygons, Profile::Hiking, &[]);
+    let statements = query.matches("(poly:\"46 6 46 6 46 6\");").count();
+    assert_eq!(statements, Profile::Hiking.overpass_selectors().len() + 1);
+}
+
+#[test]
+fn query_asks_for_interests_mapped_as_shapes() {
+    let polygons = [vec![
+        Node::new(5.76, 45.18),
+        Node::new(5.77, 45.18),
+        Node::new(5.77, 45.19),
+    ]];
+    let key_values = [
+        ("amenity".to_owned(), "toilets".to_owned()),
+        (String::new(), String::new()),
+    ];
+    let query = overpass_query(&polygons, Profile::Hiking, &key_values);
+    assert!(query.contains("way[\"amenity\"=\"toilets\"](poly:"));
+    assert!(query.contains("rel[\"amenity\"=\"toilets\"](poly:"));
+    assert!(!query.contains("[\"\"=\"\"]"));
+}
@@ -0,0 +1,652 @@
+// what we write must decode back to the same values.
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+};
+
+use itertools::Itertools;
+
+use gps::{
+    get_polygon, load_gps_from_string, load_heights, load_instructions, load_path,
+    map::{read_block, save_gps_header, DEFAULT_SIDE, TILES_PER_GROUP},
+    maps_and_interests_from_string, save_heights, save_instructions, save_path, EdgeAttributes,
+    GpsFile, Instruction, Map, Node, Profile, TurnDirection, GPX_WAYPOINT_INTEREST,
+    PATH_RESOLUTION,
+};
+
+// a street crossing the same tiles as the path
+const OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.1855" lon="5.7655"/>
+  <node id="2" lat="45.1866" lon="5.7680"/>
+  <node id="3" lat="45.1872" lon="5.7712">
+    <tag k="amenity" v="drinking_water"/>
+  </node>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="residential"/>
+    <tag k="name" v="Rue des Ecoles"/>
+  </way>
+</osm>
+"#;
+
+fn key_values() -> Vec<(String, String)> {
+    vec![("amenity".to_owned(), "drinking_water".to_owned())]
+}
+
+// a zigzag too sharp to be simplified, with heights and a commented point
+fn gpx_points() -> Vec<(Node, f64)> {
+    (0..13)
+        .map(|i| {
+            let point = Node::new(5.766 + i as f64 * 4e-4, 45.186 + (i % 2) as f64 * 8e-4);
+            (point, 200. + i as f64 * 3.)
+        })
+        .collect()
+}
+
+fn gpx() -> String {
+    let points = gpx_points()
+        .iter()
+        .enumerate()
+        .map(|(i, (p, height))| {
+            let comment = if i == 5 { "<cmt>turn</cmt>" } else { "" };
+            format!(
+                "<trkpt lat=\"{}\" lon=\"{}\"><ele>{height}</ele>{comment}</trkpt>",
+                p.y, p.x
+            )
+        })
+        .collect::<String>();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk><name>zigzag</name><trkseg>{points}</trkseg></trk>
+</gpx>"#
+    )
+}
+
+// maps are built from hash maps so ways order changes from one build to another,
+// we compare the decoded ways of each tile and of each street
+fn assert_same_map(decoded: &Map, expected: &Map) {
+    assert_eq!(decoded.color, expected.color);
+    assert_eq!(decoded.first_tile, expected.first_tile);
+    assert_eq!(decoded.grid_size, expected.grid_size);
+    assert_eq!(decoded.side, expected.side);
+    assert_eq!(decoded.attributes.len(), expected.attributes.len());
+    assert_eq!(tiles_ways(decoded), tiles_ways(expected));
+    assert_eq!(streets_ways(decoded), streets_ways(expected));
+}
+
+type Way = ([Node; 2], Option<EdgeAttributes>);
+
+fn tiles_ways(map: &Map) -> Vec<HashSet<Way>> {
+    (0..map.tiles_sizes_prefix.len() as u32)
+        .map(|tile| map.tile_ways(tile).map(|(_, n, a)| (n, a)).collect())
+        .collect()
+}
+
+fn streets_ways(map: &Map) -> HashMap<String, HashSet<Way>> {
+    let ways = (0..map.tiles_sizes_prefix.len() as u32)
+        .flat_map(|tile| map.tile_ways(tile).map(|(id, n, a)| (id, (n, a))))
+        .collect::<HashMap<_, _>>();
+    map.streets
+        .iter()
+        .map(|(name, ids)| (name.clone(), ids.iter().map(|id| ways[id]).collect()))
+        .collect()
+}
+
+fn near(a: &Node, b: &Node, tolerance: f64) -> bool {
+    (a.x - b.x).abs() <= tolerance && (a.y - b.y).abs() <= tolerance
+}
+
+#[tokio::test]
+async fn written_gps_decodes_to_the_same_values() {
+    let mut gps = load_gps_from_string(&gpx(), false).unwrap();
+    let source = |_: &str| -> Result<String, Box<dyn Error>> { Ok(OSM.to_owned()) };
+    let no_map: Option<&str> = None;
+    gps.request_maps_with(&key_values(), no_map, &source).await;
+    let mut binary = Vec::new();
+    gps.write_gps(&mut binary).unwrap();
+    let decoded = GpsFile::read(&mut binary.as_slice()).unwrap();
+
+    // the path is rounded to PATH_RESOLUTION
+    let expected = gpx_points();
+    let path = decoded.path.unwrap();
+    assert_eq!(path.len(), expected.len());
+    assert!(path
+        .iter()
+        .zip(&expected)
+        .all(|(p, (e, _))| near(p, e, PATH_RESOLUTION / 2.)));
+    assert!(decoded.breaks.is_empty());
+    assert_eq!(
+        decoded.waypoints.unwrap(),
+        [path[0], path[5], path[12]].into_iter().collect()
+    );
+    assert_eq!(
+        decoded.heights.unwrap(),
+        expected.iter().map(|(_, h)| *h).collect::<Vec<_>>()
+    );
+
+    // the tiled path comes before the map
+    let points = expected.iter().map(|(p, _)| *p).collect::<Vec<_>>();
+    assert_eq!(decoded.maps.len(), 2);
+    assert_same_map(
+        &decoded.maps[0],
+        &Map::from_path(points.clone(), &[], DEFAULT_SIDE),
+    );
+    let (mut maps, _) =
+        maps_and_interests_from_string(OSM, &key_values(), Profile::default(), false, DEFAULT_SIDE)
+            .unwrap();
+    maps[0].attributes.clear();
+    assert_same_map(&decoded.maps[1], &maps[0]);
+    assert!(decoded.maps[1].streets.contains_key("Rue des Ecoles"));
+
+    // interests are rounded in their tile, waypoints come with them
+    let tolerance = DEFAULT_SIDE / 255.;
+    let mut expected_interests = vec![(1, Node::new(5.7712, 45.1872))];
+    expected_interests.extend([0, 5, 12].map(|i| (0, points[i])));
+    assert_eq!(decoded.interests.len(), expected_interests.len());
+    for (interest, node) in &expected_interests {
+        assert!(decoded
+            .interests
+            .iter()
+            .any(|(i, n)| i == interest && near(n, node, tolerance)));
+    }
+    assert!(decoded
+        .metadata
+        .contains(&("name".to_owned(), "zigzag".to_owned())));
+}
+
+// a square loop with a point every ten seconds, ending where it starts
+#[tokio::test]
+async fn loop_times_keep_increasing() {
+    let corners = [
+        (45.186, 5.766),
+        (45.186, 5.770),
+        (45.1868, 5.770),
+        (45.1868, 5.766),
+        (45.186, 5.766),
+    ];
+    let points = corners
+        .iter()
+        .enumerate()
+        .map(|(i, (lat, lon))| {
+            format!(
+                "<trkpt lat=\"{lat}\" lon=\"{lon}\"><time>2024-05-01T10:00:{:02}Z</time></trkpt>",
+                i * 10
+            )
+        })
+        .collect::<String>();
+    let gpx = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk><trkseg>{points}</trkseg></trk>
+</gpx>"#
+    );
+    let mut gps = load_gps_from_string(&gpx, false).unwrap();
+    let source = |_: &str| -> Result<String, Box<dyn Error>> { Ok(OSM.to_owned()) };
+    let no_map: Option<&str> = None;
+    gps.request_maps_with(&[], no_map, &source).await;
+    let mut binary = Vec::new();
+    gps.write_gps(&mut binary).unwrap();
+    let decoded = GpsFile::read(&mut binary.as_slice()).unwrap();
+
+    assert_eq!(decoded.path.unwrap().len(), corners.len());
+    assert_eq!(decoded.times.unwrap(), vec![0, 10, 20, 30, 40]);
+}
+
+// track segments of (lat, lon, commented) points
+fn segments_gpx(segments: &[&[(f64, f64, bool)]]) -> String {
+    let segments = segments
+        .iter()
+        .map(|points| {
+            let points = points
+                .iter()
+                .map(|(lat, lon, commented)| {
+                    let comment = if *commented { "<cmt>turn</cmt>" } else { "" };
+                    format!("<trkpt lat=\"{lat}\" lon=\"{lon}\">{comment}</trkpt>")
+                })
+                .collect::<String>();
+            format!("<trkseg>{points}</trkseg>")
+        })
+        .collect::<String>();
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<gpx version="1.1" creator="test" xmlns="http://www.topografix.com/GPX/1/1">
+  <trk>{segments}</trk>
+</gpx>"#
+    )
+}
+
+// segments ends are waypoints but there is no turn between segments
+#[tokio::test]
+async fn no_instructions_across_segments() {
+    let gpx = segments_gpx(&[
+        &[
+            (45.186, 5.766, false),
+            (45.1868, 5.767, true),
+            (45.186, 5.768, true),
+        ],
+        &[
+            (45.1868, 5.770, true),
+            (45.186, 5.771, false),
+            (45.1868, 5.772, false),
+        ],
+    ]);
+    let mut gps = load_gps_from_string(&gpx, false).unwrap();
+    let source = |_: &str| -> Result<String, Box<dyn Error>> { Ok(OSM.to_owned()) };
+    let no_map: Option<&str> = None;
+    gps.request_maps_with(&[], no_map, &source).await;
+    let mut binary = Vec::new();
+    gps.write_gps(&mut binary).unwrap();
+    let decoded = GpsFile::read(&mut binary.as_slice()).unwrap();
+
+    assert_eq!(decoded.path.unwrap().len(), 6);
+    assert_eq!(decoded.breaks, [3]);
+    let points = decoded
+        .instructions
+        .iter()
+        .map(|i| i.point)
+        .collect::<Vec<_>>();
+    assert_eq!(points, [1]);
+}
+
+#[test]
+fn distant_segments_get_their_own_polygon() {
+    let near = segments_gpx(&[
+        &[(45.186, 5.766, false), (45.186, 5.768, false)],
+        &[(45.187, 5.770, false), (45.187, 5.772, false)],
+    ]);
+    let far = segments_gpx(&[
+        &[(45.186, 5.766, false), (45.186, 5.768, false)],
+        &[(46.186, 6.766, false), (46.186, 6.768, false)],
+    ]);
+    let polygons_number = |gpx: &str| {
+        let polygon = get_polygon(&load_gps_from_string(gpx, false).unwrap());
+        // polygons are separated by a NaN pair
+        polygon.iter().filter(|c| c.is_nan()).count() / 2 + 1
+    };
+    assert_eq!(polygons_number(&near), 1);
+    assert_eq!(polygons_number(&far), 2);
+}
+
+#[test]
+fn invalid_gpx_files_are_errors() {
+    assert!(load_gps_from_string("not a gpx file", false).is_err());
+    let empty = segments_gpx(&[]);
+    assert!(load_gps_from_string(&empty, false).is_err());
+}
+
+#[tokio::test]
+async fn gpx_waypoints_away_from_the_path_have_their_own_type() {
+    let col = Node::new(5.768, 45.188);
+    let gpx = gpx().replace(
+        "<trk>",
+        &format!(
+            "<wpt lat=\"{}\" lon=\"{}\"><name>col</name></wpt><trk>",
+            col.y, col.x
+        ),
+    );
+    let mut gps = load_gps_from_string(&gpx, false).unwrap();
+    let source = |_: &str| -> Result<String, Box<dyn Error>> { Ok(OSM.to_owned()) };
+    let no_map: Option<&str> = None;
+    gps.request_maps_with(&key_values(), no_map, &source).await;
+    let mut binary = Vec::new();
+    gps.write_gps(&mut binary).unwrap();
+    let decoded = GpsFile::read(&mut binary.as_slice()).unwrap();
+
+    let tolerance = DEFAULT_SIDE / 255.;
+    let gpx_interests = decoded
+        .interests
+        .iter()
+        .filter(|(interest, _)| *interest == GPX_WAYPOINT_INTEREST)
+        .collect::<Vec<_>>();
+    assert_eq!(gpx_interests.len(), 1);
+    assert!(near(&gpx_interests[0].1, &col, tolerance));
+    // path waypoints keep type 0
+    assert_eq!(decoded.interests.iter().filter(|(i, _)| *i == 0).count(), 3);
+}
+
+// parallel streets over the path, enough of them to get several blocks of names
+fn streets_osm() -> String {
+    let mut osm =
+        String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<osm version=\"0.6\">\n");
+    for street in 0..20 {
+        let lat = 45.1855 + street as f64 * 1e-4;
+        for (i, lon) in [5.7655, 5.7680, 5.7712].iter().enumerate() {
+            let id = street * 3 + i + 1;
+            osm.push_str(&format!(
+                "  <node id=\"{id}\" lat=\"{lat}\" lon=\"{lon}\"/>\n"
+            ));
+        }
+        let name = if street == 0 {
+            "Rue des Écoles".to_owned()
+        } else {
+            format!("Street {street:02}")
+        };
+        osm.push_str(&format!(
+            "  <way id=\"{}\"><nd ref=\"{}\"/><nd ref=\"{}\"/><nd ref=\"{}\"/>\
+             <tag k=\"highway\" v=\"residential\"/><tag k=\"name\" v=\"{name}\"/></way>\n",
+            100 + street,
+            street * 3 + 1,
+            street * 3 + 2,
+            street * 3 + 3,
+        ));
+    }
+    osm.push_str("</osm>\n");
+    osm
+}
+
+#[tokio::test]
+async fn streets_keep_their_names_and_ways() {
+    let mut gps = load_gps_from_string(&gpx(), false).unwrap();
+    let osm = streets_osm();
+    let source = |_: &str| -> Result<String, Box<dyn Error>> { Ok(osm.clone()) };
+    let no_map: Option<&str> = None;
+    gps.request_maps_with(&[], no_map, &source).await;
+    let mut binary = Vec::new();
+    gps.write_gps(&mut binary).unwrap();
+    let decoded = GpsFile::read(&mut binary.as_slice()).unwrap();
+
+    let streets = &gps.maps()[0].streets;
+    assert_eq!(streets.len(), 20);
+    // accents are removed from names
+    let expected = streets
+        .iter()
+        .map(|(name, ways)| (name.replace('É', "E"), ways.clone()))
+        .collect::<HashMap<_, _>>();
+    assert!(expected.contains_key("Rue des Ecoles"));
+    assert_eq!(decoded.maps[1].streets, expected);
+}
+
+#[test]
+fn long_paths_are_stored_whole() {
+    let points = (0..70_000)
+        .map(|i| Node::new(5.7 + i as f64 * 3e-6, 45.1 + (i % 100) as f64 * 7e-6))
+        .collect::<Vec<_>>();
+    let waypoints = [0, 65_535, 65_536, 69_999]
+        .map(|i| points[i])
+        .into_iter()
+        .collect::<HashSet<_>>();
+    let breaks = vec![1, 40_000, 66_000];
+    let mut block = Vec::new();
+    save_path(&points, &waypoints, &breaks, &mut block).unwrap();
+    let (_, content) = read_block(&mut block.as_slice(), 0).unwrap();
+    let (decoded, decoded_waypoints, decoded_breaks) = load_path(&mut content.as_slice()).unwrap();
+
+    assert_eq!(decoded.len(), points.len());
+    assert!(decoded
+        .iter()
+        .zip(&points)
+        .all(|(d, p)| near(d, p, PATH_RESOLUTION / 2.)));
+    assert_eq!(
+        decoded_waypoints,
+        [0, 65_535, 65_536, 69_999]
+            .map(|i| decoded[i])
+            .into_iter()
+            .collect()
+    );
+    assert_eq!(decoded_breaks, breaks);
+}
+
+// a crossroad where a bicycle route turns, its layer repeats the streets ways
+const ROUTE_CROSSROAD: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="45.186" lon="5.7655"/>
+  <node id="2" lat="45.186" lon="5.768"/>
+  <node id="3" lat="45.186" lon="5.7712"/>
+  <node id="4" lat="45.1875" lon="5.768"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <tag k="highway" v="residential"/>
+    <tag k="name" v="Rue des Ecoles"/>
+  </way>
+  <way id="11">
+    <nd ref="2"/>
+    <nd ref="4"/>
+    <tag k="highway" v="residential"/>
+    <tag k="name" v="Rue Haute"/>
+  </way>
+  <relation id="20">
+    <member type="way" ref="10" role=""/>
+    <member type="way" ref="11" role=""/>
+    <tag k="type" v="route"/>
+    <tag k="route" v="bicycle"/>
+    <tag k="ref" v="V63"/>
+  </relation>
+</osm>
+"#;
+
+#[tokio::test]
+async fn route_layers_do_not_add_branches() {
+    let gpx = segments_gpx(&[&[
+        (45.186, 5.7655, false),
+        (45.186, 5.768, true),
+        (45.1875, 5.768, false),
+    ]]);
+    let mut gps = load_gps_from_string(&gpx, false).unwrap();
+    let source = |_: &str| -> Result<String, Box<dyn Error>> { Ok(ROUTE_CROSSROAD.to_owned()) };
+    let no_map: Option<&str> = None;
+    gps.request_maps_with(&[], no_map, &source).await;
+    assert_eq!(gps.maps().len(), 2);
+    let mut binary = Vec::new();
+    gps.write_gps(&mut binary).unwrap();
+    let decoded = GpsFile::read(&mut binary.as_slice()).unwrap();
+
+    // we can go straight or turn left in rue haute
+    assert_eq!(decoded.instructions.len(), 1);
+    let instruction = &decoded.instructions[0];
+    assert_eq!(instruction.point, 1);
+    assert_eq!(instruction.direction, TurnDirection::Left);
+    assert_eq!(instruction.branch, 1);
+    assert_eq!(instruction.branches, 2);
+    assert_eq!(instruction.street.as_deref(), Some("Rue Haute"));
+}
+
+#[test]
+fn instructions_keep_all_their_fields() {
+    let instructions = vec![
+        Instruction {
+            point: 0,
+            direction: TurnDirection::SlightRight,
+            angle: -35,
+            branch: 0,
+            branches: 3,
+            distance_to_next: 250,
+            street: Some("Rue des Ecoles".to_owned()),
+        },
+        Instruction {
+            point: 70_000,
+            direction: TurnDirection::UTurn,
+            angle: i16::MIN,
+            branch: u8::MAX,
+            branches: u8::MAX,
+            distance_to_next: u32::MAX,
+            street: None,
+        },
+        Instruction {
+            point: 70_001,
+            direction: TurnDirection::Left,
+            angle: 90,
+            branch: 2,
+            branches: 4,
+            distance_to_next: 0,
+            street: Some("Chemin du Lac".to_owned()),
+        },
+    ];
+    let mut block = Vec::new();
+    save_instructions(&instructions, &mut block).unwrap();
+    let (_, content) = read_block(&mut block.as_slice(), 0).unwrap();
+    assert_eq!(
+        load_instructions(&mut content.as_slice()).unwrap(),
+        instructions
+    );
+}
+
+#[test]
+fn heights_are_interpolated_and_can_be_decimeters() {
+    // evenly spaced points, heights only known at points 2 and 6
+    let points = (0..9)
+        .map(|i| Node::new(5.7 + i as f64 * 1e-3, 45.1))
+        .collect::<Vec<_>>();
+    let heights = [(points[2], 100.3), (points[6], 101.1)]
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    let expected = [
+        100.3, 100.3, 100.3, 100.5, 100.7, 100.9, 101.1, 101.1, 101.1,
+    ];
+
+    let decode = |decimeters| {
+        let mut block = Vec::new();
+        save_heights(&points, &heights, decimeters, &mut block).unwrap();
+        let (_, content) = read_block(&mut block.as_slice(), 0).unwrap();
+        load_heights(points.len(), &mut content.as_slice()).unwrap()
+    };
+    let decimeters = decode(true);
+    assert_eq!(decimeters.len(), expected.len());
+    assert!(decimeters
+        .iter()
+        .zip(expected)
+        .all(|(d, e)| (d - e).abs() < 1e-6));
+    let meters = decode(false);
+    assert!(meters
+        .iter()
+        .zip(expected)
+        .all(|(m, e)| m.fract() == 0. && (m - e).abs() <= 0.5 + 1e-6));
+}
+
+#[test]
+fn overcrowded_tiles_cannot_be_saved() {
+    // more than u8::MAX sub-tiles of u8::MAX ways in a single tile
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    let mut random = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        (seed % 1000) as f64 / 1000.
+    };
+    let (x, y) = (4324. * DEFAULT_SIDE, 33889. * DEFAULT_SIDE);
+    let points = (0..70_000)
+        .map(|_| {
+            Node::new(
+                x + (0.05 + 0.9 * random()) * DEFAULT_SIDE,
+                y + (0.05 + 0.9 * random()) * DEFAULT_SIDE,
+            )
+        })
+        .collect::<Vec<_>>();
+    let map = Map::from_path(points, &[], DEFAULT_SIDE);
+    assert!(map.sub_tiles_ends[&0].len() > u8::MAX as usize);
+    let error = map.save_tiles(&mut Vec::new(), false).unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn tiles_numbers_go_beyond_u16() {
+    // a diagonal across a 301x301 tiles grid
+    let start = Node::new(4000.5 * DEFAULT_SIDE, 33000.5 * DEFAULT_SIDE);
+    let end = Node::new(4300.5 * DEFAULT_SIDE, 33300.5 * DEFAULT_SIDE);
+    let mut map = Map::from_path(vec![start, end], &[], DEFAULT_SIDE);
+    assert_eq!(map.grid_size, (301, 301));
+    let far_ways = (65_536..map.tiles_sizes_prefix.len() as u32)
+        .flat_map(|tile| map.tile_ways_ids(tile).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert!(!far_ways.is_empty());
+    map.streets.insert("Far away".to_owned(), far_ways);
+
+    let mut binary = Vec::new();
+    map.save_tiles(&mut binary, false).unwrap();
+    map.save_streets(&mut binary).unwrap();
+    let reader = &mut binary.as_slice();
+    let (_, tiles) = read_block(reader, 0).unwrap();
+    let (_, streets) = read_block(reader, 1).unwrap();
+    let mut decoded = Map::load_tiles(&mut tiles.as_slice(), false).unwrap();
+    decoded.load_streets(&mut streets.as_slice()).unwrap();
+
+    assert_eq!(decoded.tiles_sizes_prefix, map.tiles_sizes_prefix);
+    assert_eq!(decoded.binary_ways, map.binary_ways);
+    assert_eq!(decoded.streets, map.streets);
+    assert_same_map(&decoded, &map);
+}
+
+#[test]
+fn compressed_groups_decompress_alone() {
+    let points = (0..200)
+        .map(|i| Node::new(5.7 + i as f64 * 2e-4, 45.1 + (i % 7) as f64 * 3e-4))
+        .collect::<Vec<_>>();
+    let map = Map::from_path(points, &[], DEFAULT_SIDE);
+    let content = |compressed| {
+        let mut block = Vec::new();
+        map.save_tiles(&mut block, compressed).unwrap();
+        read_block(&mut block.as_slice(), 0).unwrap().1
+    };
+    let compressed = content(true);
+    let decoded = Map::load_tiles(&mut compressed.as_slice(), true).unwrap();
+    assert_eq!(decoded.binary_ways, map.binary_ways);
+    assert_same_map(&decoded, &map);
+
+    // both blocks share everything before the ways
+    let header_size = content(false).len() - map.binary_ways.len();
+    let groups = &compressed[header_size..];
+    assert_eq!(groups[0] as usize, TILES_PER_GROUP);
+    let non_empty_tiles = (0..map.tiles_sizes_prefix.len() as u32)
+        .filter(|tile| map.tile_ways_number(*tile) > 0)
+        .collect::<Vec<_>>();
+    let groups_number = non_empty_tiles.len().div_ceil(TILES_PER_GROUP);
+    assert!(groups_number > 3);
+    let ends = groups[1..]
+        .chunks(4)
+        .take(groups_number)
+        .map(|end| u32::from_le_bytes(end.try_into().unwrap()) as usize)
+        .collect::<Vec<_>>();
+    let compressed_groups = &groups[1 + 4 * groups_number..];
+
+    // decompress the third group only, it holds the ways of its tiles
+    let group = &compressed_groups[ends[1]..ends[2]];
+    let expected = non_empty_tiles[2 * TILES_PER_GROUP..3 * TILES_PER_GROUP]
+        .iter()
+        .flat_map(|tile| map.tile_binary(*tile).to_vec())
+        .collect::<Vec<_>>();
+    let mut output = vec![0; 2 * expected.len()];
+    let config = heatshrink::Config::new(8, 6).unwrap();
+    let ways = heatshrink::decode(group, &mut output, &config).unwrap();
+    assert_eq!(ways, expected);
+
+    // swap two groups of different sizes: the total size is right but not the groups spans
+    let spans = non_empty_tiles
+        .chunks(TILES_PER_GROUP)
+        .map(|tiles| {
+            tiles
+                .iter()
+                .map(|tile| map.tile_binary(*tile).len())
+                .sum::<usize>()
+        })
+        .collect::<Vec<_>>();
+    let swapped = (0..groups_number - 1)
+        .find(|group| spans[*group] != spans[group + 1])
+        .unwrap();
+    let mut groups_data = std::iter::once(0)
+        .chain(ends.iter().copied())
+        .tuple_windows()
+        .map(|(start, end)| &compressed_groups[start..end])
+        .collect::<Vec<_>>();
+    groups_data.swap(swapped, swapped + 1);
+    let mut mixed = compressed[..header_size + 1].to_vec();
+    let mut end = 0;
+    for group in &groups_data {
+        end += group.len() as u32;
+        mixed.extend(end.to_le_bytes());
+    }
+    mixed.extend(groups_data.concat());
+    assert!(Map::load_tiles(&mut mixed.as_slice(), true).is_err());
+}
+
+#[test]
+fn blocks_number_must_fit_in_the_header() {
+    let mut header = Vec::new();
+    save_gps_header(u16::MAX as usize, &mut header).unwrap();
+    assert_eq!(header[6..], u16::MAX.to_le_bytes());
+    assert!(save_gps_header(u16::MAX as usize + 1, &mut Vec::new()).is_err());
+}